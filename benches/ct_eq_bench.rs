@@ -0,0 +1,53 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rand::{rng, Rng};
+
+use secretmangle::{MangledBox, with_two};
+
+fn random_box<const N: usize>(value: [u8; N]) -> MangledBox<[u8; N]>
+where
+    [u8; N]: bytemuck::Pod,
+{
+    let mut box_ = MangledBox::new();
+    box_.with_unmangled(|p| unsafe { p.write(value) });
+    box_
+}
+
+fn internal_bench_ct_eq<const N: usize>(c: &mut Criterion)
+where
+    [u8; N]: bytemuck::Pod,
+{
+    let mut rng = rng();
+    let value: [u8; N] = std::array::from_fn(|_| rng.random());
+    let mut a = random_box(value);
+    let mut b = random_box(value);
+
+    let mut group = c.benchmark_group(format!("eq_{}b", N));
+    group.throughput(Throughput::Bytes(N as u64));
+
+    group.bench_function("ct_eq_masked", |bench| {
+        bench.iter(|| black_box(black_box(&a).ct_eq(black_box(&b))));
+    });
+
+    group.bench_function("unmangle_and_eq", |bench| {
+        bench.iter(|| black_box(with_two(black_box(&mut a), black_box(&mut b), |x, y| x == y)));
+    });
+
+    group.finish();
+}
+
+fn bench_ct_eq(c: &mut Criterion) {
+    internal_bench_ct_eq::<16>(c);
+    internal_bench_ct_eq::<32>(c);
+    internal_bench_ct_eq::<64>(c);
+    internal_bench_ct_eq::<256>(c);
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(std::time::Duration::from_millis(500))
+        .measurement_time(std::time::Duration::from_secs(1));
+    targets = bench_ct_eq
+);
+
+criterion_main!(benches);
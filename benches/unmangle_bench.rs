@@ -0,0 +1,47 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use secretmangle::MangledBox;
+
+/// Number of boxes unmangled together in the batched variant.
+const BATCH: usize = 8;
+
+fn internal_bench_unmangle<const N: usize>(c: &mut Criterion)
+where
+    [u8; N]: bytemuck::Pod,
+{
+    let mut single = MangledBox::<[u8; N]>::new();
+    let mut batch: Vec<_> = (0..BATCH).map(|_| MangledBox::<[u8; N]>::new()).collect();
+
+    let mut group = c.benchmark_group(format!("unmangle_{}b", N));
+    group.throughput(Throughput::Bytes(N as u64));
+
+    group.bench_function("with_unmangled", |bench| {
+        bench.iter(|| black_box(&mut single).with_unmangled(black_box));
+    });
+
+    // Per box, so that the saving of one fence per batch shows up directly.
+    group.throughput(Throughput::Bytes((N * BATCH) as u64));
+    group.bench_function(format!("with_unmangled_batched_{BATCH}"), |bench| {
+        bench.iter(|| MangledBox::with_unmangled_batched(black_box(&mut batch), |p| black_box(p.len())));
+    });
+
+    group.finish();
+}
+
+fn bench_unmangle(c: &mut Criterion) {
+    internal_bench_unmangle::<16>(c);
+    internal_bench_unmangle::<64>(c);
+    internal_bench_unmangle::<256>(c);
+    internal_bench_unmangle::<1024>(c);
+    internal_bench_unmangle::<4096>(c);
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(std::time::Duration::from_millis(500))
+        .measurement_time(std::time::Duration::from_secs(1));
+    targets = bench_unmangle
+);
+
+criterion_main!(benches);
@@ -94,6 +94,7 @@ fn internal_bench_full<const N: usize>(c: &mut Criterion) {
 // Benchmark for different data sizes
 fn bench_xor_chunks(c: &mut Criterion) {
     internal_bench_full::<1>(c);
+    internal_bench_full::<8>(c);
     internal_bench_full::<16>(c);
     internal_bench_full::<64>(c);
     internal_bench_full::<256>(c);
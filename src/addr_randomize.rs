@@ -0,0 +1,97 @@
+//! Allocator placing each block at a random offset within an over-allocated region, so
+//! that the addresses of masked data and keys are less predictable across runs.
+
+use std::alloc::{AllocError, Allocator, Global, Layout};
+use std::mem::{MaybeUninit, size_of};
+use std::ptr::NonNull;
+
+use crate::keygen;
+
+/// Upper bound on the random offset added to each block.
+const SPREAD: usize = 4096;
+
+/// Allocator shifting every block by a random multiple of its alignment, up to one page.
+///
+/// Use it through the `new_in` constructors, e.g. `MangledBox::<u64, _>::new_in(RandomizedAddr)`;
+/// both the data and the key allocations are randomized then.
+///
+/// This is a hardening measure with a memory cost: every allocation grows by a page plus
+/// a pointer-sized header (aligned up to the block's alignment), so a box of a small `T`
+/// takes two pages instead of a few bytes. The offset takes at most 12 bits of entropy
+/// and does not hide which page the block is in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomizedAddr;
+
+impl RandomizedAddr {
+    /// Layout of the whole region backing a block with `layout`, and the offset of the
+    /// header-sized gap reserved in front of the block.
+    fn region(layout: Layout) -> Result<(Layout, usize), AllocError> {
+        let header = layout.align().max(size_of::<*mut u8>());
+        let size = layout.size()
+            .checked_add(header + SPREAD)
+            .ok_or(AllocError)?;
+        let region = Layout::from_size_align(size, header).map_err(|_| AllocError)?;
+        Ok((region, header))
+    }
+}
+
+unsafe impl Allocator for RandomizedAddr {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (region, header) = Self::region(layout)?;
+        let base = Global.allocate(region)?.cast::<u8>();
+
+        let mut random = [MaybeUninit::uninit(); size_of::<usize>()];
+        let random = usize::from_ne_bytes(keygen::fill(&mut random).try_into().unwrap());
+        let offset = header + random % (SPREAD / layout.align() + 1) * layout.align();
+        // ^ at most `header + SPREAD`, a multiple of the block's alignment
+
+        // # Safety
+        // 1. `offset + layout.size()` fits in the region, see [`Self::region`]
+        // 2. `offset >= header >= size_of::<*mut u8>()`, so the base pointer fits in front
+        //    of the block and stays within the region
+        unsafe {
+            let block = base.add(offset);
+            block.sub(size_of::<*mut u8>()).cast::<*mut u8>().write_unaligned(base.as_ptr());
+            Ok(NonNull::slice_from_raw_parts(block, layout.size()))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // # Safety
+        // `ptr` was returned by [`Self::allocate`] with the same `layout`, so it is
+        // preceded by the base pointer of a region allocated with [`Self::region`].
+        unsafe {
+            let base = ptr.sub(size_of::<*mut u8>()).cast::<*mut u8>().read_unaligned();
+            let (region, _) = Self::region(layout).unwrap_unchecked();
+            Global.deallocate(NonNull::new_unchecked(base), region);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::MangledBox;
+
+    #[derive(bytemuck::NoUninit, Clone, Copy, PartialEq, Debug)]
+    #[repr(C, align(64))]
+    struct Align64([u8; 64]);
+
+    #[test]
+    fn aligned_and_round_trips() {
+        let mut offsets = HashSet::new();
+        for i in 0..16u8 {
+            let mut box_ = MangledBox::<Align64, _>::new_in(RandomizedAddr);
+            box_.with_unmangled(|p| {
+                assert_eq!(p.as_ptr().align_offset(64), 0, "alignment not preserved");
+                offsets.insert(p.as_ptr().addr() % SPREAD);
+                unsafe { p.write(Align64([i; 64])) };
+            });
+            box_.rekey();
+            box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, Align64([i; 64])));
+        }
+        assert!(offsets.len() > 1, "offsets are not randomized");
+    }
+}
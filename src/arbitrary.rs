@@ -2,8 +2,18 @@ pub mod xor_intrinsic;
 
 use std::sync::atomic::{fence, Ordering};
 use std::marker::PhantomData;
-use std::mem::MaybeUninit;
+use std::mem::{MaybeUninit, size_of};
 use std::ptr::NonNull;
+use std::ops::Range;
+use std::pin::Pin;
+use std::marker::Freeze;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::alloc::{Allocator, Global};
+
+use bytemuck::{NoUninit, Pod};
+
+use crate::{AbortOnUnwind, MangledBox, SecretContainer, keygen};
+use crate::nouninit::with_diff_scratch;
 
 /// XORs the data behind first pointer using key from second pointer.
 /// The mangling operation is guaranteed to not be reordered after
@@ -18,12 +28,65 @@ use std::ptr::NonNull;
 ///
 /// No requirements on initialization status are made.
 unsafe fn xor_chunks<T>(data: *mut u8, key: *const u8) {
+    if size_of::<T>() == 0 {
+        // No bytes to mask, so no access to order either.
+        return;
+    }
     unsafe {
         xor_intrinsic::xor_chunks_intrinsic_baseline::<T>(data, key);
     }
     fence(Ordering::SeqCst);
 }
 
+/// XORs `len` bytes behind the first pointer using key from the second pointer,
+/// with the same ordering guarantee as [`xor_chunks`].
+///
+/// # Safety
+/// - `data` and `key` must have at least `len` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+///
+/// No requirements on initialization status are made.
+unsafe fn xor_range(data: *mut u8, key: *const u8, len: usize) {
+    unsafe {
+        xor_intrinsic::xor_chunks_len(data, key, len);
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// Structure that handles remangling the pointed-to memory when dropped
+/// (both upon panic and successful completion of the unmangled scope).
+/// Its fields are private to this module because constructing it is
+/// only sound when the [`xor_chunks`] preconditions hold.
+struct RemangleGuard<T> {
+    data: *mut u8,
+    key: *const u8,
+    token: PhantomData<T>,
+}
+
+impl<T> Drop for RemangleGuard<T> {
+    fn drop(&mut self) {
+        let bomb = AbortOnUnwind("failed to remangle the contents");
+        unsafe { xor_chunks::<T>(self.data, self.key) }
+        bomb.defuse();
+    }
+}
+
+/// Structure that handles remangling the pointed-to range when dropped, like
+/// [`RemangleGuard`] but for a part of the contents ([`xor_range`] preconditions).
+struct RemangleRangeGuard {
+    data: *mut u8,
+    key: *const u8,
+    len: usize,
+}
+
+impl Drop for RemangleRangeGuard {
+    fn drop(&mut self) {
+        let bomb = AbortOnUnwind("failed to remangle the contents");
+        unsafe { xor_range(self.data, self.key, self.len) }
+        bomb.defuse();
+    }
+}
+
 /// Utility for masking a structure in program's heap with a random key,
 /// supporting an arbitrary content type.
 ///
@@ -34,44 +97,166 @@ unsafe fn xor_chunks<T>(data: *mut u8, key: *const u8) {
 /// It is recommended to use [`std::clone::CloneToUninit`] to initialize
 /// the contents of the box rather than constructing it on stack, since the
 /// latter option might leave some trace of value being masked.
-pub struct MangledBoxArbitrary<T> {
+///
+/// # Thread safety
+/// As with [`crate::MangledBox`], [`Send`] and [`Sync`] are derived from `T` and every
+/// access to the contents requires `&mut self`. A `T` that is [`Send`] but not [`Sync`]
+/// keeps the box from being [`Sync`]:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use secretmangle::MangledBoxArbitrary;
+///
+/// fn ensure_sync<T: Sync>(_v: &T) {}
+/// ensure_sync(&MangledBoxArbitrary::<Cell<u8>>::new());
+/// ```
+///
+/// # Unwind safety
+/// Like [`crate::MangledBox`], the box is [`UnwindSafe`] and [`RefUnwindSafe`] regardless
+/// of `T`: the contents are remangled before a panic leaves any unmangling method, and
+/// a shared reference gives no access to them. A `T` left half-updated by a panicking
+/// closure is still the caller's concern, exactly as with a plain `&mut T`.
+///
+/// # Enums and niches
+/// Any `T` is supported, including enums and types with niches such as `Option<NonZeroU32>`
+/// or `Option<&U>`, whose discriminant is encoded in otherwise invalid bit patterns. While
+/// masked, the bytes may form no valid `T` at all, e.g. a discriminant naming no variant;
+/// the masked form is only ever handled as bytes, and XOR round-trips exactly, so the
+/// closures always see the value that was written. [`crate::MangledBox`] accepts fieldless
+/// enums too where they are [`NoUninit`], but enums with fields generally are not.
+///
+/// # Address stability
+/// The contents live in their own heap allocation, which stays in place when the box
+/// itself is moved, so the pointer handed to the closures is the same for the whole life
+/// of the box. Self-referential contents, with pointers fixed up after construction, thus
+/// stay valid; [`MangledBoxArbitrary::with_unmangled_pinned`] hands them out as [`Pin`].
+/// The box itself is [`Unpin`], and wrapping it in `Pin<Box<_>>` adds nothing.
+pub struct MangledBoxArbitrary<T, A: Allocator = Global> {
     /// Heap allocation with bytes mangled by XORing with `key`.
-    data: Box<MaybeUninit<T>>,
+    data: Box<MaybeUninit<T>, A>,
+
+    /// Heap allocation containing a cryptographically secure random key,
+    /// kept separate from `data` so that moving the box stays cheap.
+    key: Box<MaybeUninit<T>, A>,
+
+    /// Checksum of the two allocation addresses, see [`MangledBoxArbitrary::pointers_intact`].
+    pointer_check: usize,
 
-    /// T-sized buffer containing a cryptographically secure random key.
-    key: MaybeUninit<T>,
+    /// Whether the contents were marked initialized since construction or the last
+    /// [`MangledBoxArbitrary::drop_in_place`], checked by the latter in debug builds.
+    #[cfg(debug_assertions)]
+    initialized: bool,
 }
 
 impl<T> MangledBoxArbitrary<T> {
     /// Constructs a new [`MangledBoxArbitrary`] with a random key and arbitrary data.
     pub fn new() -> Self {
-        let data = Box::new_zeroed();
+        Self::new_in(Global)
+    }
+
+    /// Constructs a new [`MangledBoxArbitrary`] like [`Self::new`], refusing at compile time
+    /// types with interior mutability directly inside them.
+    ///
+    /// Masking relies on the data bytes changing only when the box itself XORs them.
+    /// A `T` containing an [`UnsafeCell`](std::cell::UnsafeCell) (e.g. a [`Cell`](std::cell::Cell)
+    /// or an atomic) could be mutated through a reference leaked out of an unmangled scope
+    /// while the value is masked, silently corrupting it. Such types are not [`Freeze`]:
+    ///
+    /// ```compile_fail
+    /// use std::sync::atomic::AtomicU64;
+    /// use secretmangle::MangledBoxArbitrary;
+    ///
+    /// let counter = MangledBoxArbitrary::<AtomicU64>::new_checked();
+    /// ```
+    ///
+    /// Interior mutability behind a pointer (e.g. in an `Arc<Mutex<_>>`) is fine, as only
+    /// the pointer itself is masked.
+    pub fn new_checked() -> Self
+    where
+        T: Freeze,
+    {
+        Self::new()
+    }
+}
+
+impl<T, A: Allocator + Clone> MangledBoxArbitrary<T, A> {
+    /// Constructs a new [`MangledBoxArbitrary`] with a random key and arbitrary data,
+    /// placing both the data and the key in the provided allocator.
+    pub fn new_in(alloc: A) -> Self {
+        let mut key = Box::new_uninit_in(alloc.clone());
+        keygen::fill(key.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key`] is fully initialized
+        // # Safety
+        // [`key`] is fully initialized, see above.
+        unsafe { keygen::audit_key(&*key) };
+
+        let data = Box::new_zeroed_in(alloc);
         // ^ [`data`] starts with arbitrary data from perspective of outer
         //   program; therefore we may choose anything, including that the block
         //   might had data equal to key (their XOR being zero).
 
-        let mut key = MaybeUninit::uninit();
-        getrandom::fill_uninit(key.as_bytes_mut()).expect("no keygen");
-        // ^ fill_uninit guarantees that [`key`] is fully initialized on success
+        Self {
+            pointer_check: pointer_checksum(&*data, &*key),
+            data,
+            key,
+            #[cfg(debug_assertions)]
+            initialized: false,
+        }
+    }
+}
+
+/// Mixes the addresses of the data and the key allocations with a fixed salt, so that
+/// bytes not written as a whole by [`MangledBoxArbitrary`]'s constructors are unlikely to
+/// match.
+fn pointer_checksum<T>(data: &MaybeUninit<T>, key: &MaybeUninit<T>) -> usize {
+    let (data, key) = (std::ptr::from_ref(data).addr(), std::ptr::from_ref(key).addr());
+    data.rotate_left(usize::BITS / 2) ^ key ^ 0x5EC2_E7F0
+}
+
+impl<T, A: Allocator> MangledBoxArbitrary<T, A> {
+    /// Checks the box's pointers against the checksum stored alongside them at construction.
+    ///
+    /// A corrupted pointer fails this with overwhelming probability, and so does a payload
+    /// conjured by corrupting the niche of a [`MangledOption::None`](crate::MangledOption)
+    /// into a [`MangledOption::Some`](crate::MangledOption), whose remaining bytes are
+    /// leftovers rather than a matching checksum.
+    pub(crate) fn pointers_intact(&self) -> bool {
+        self.pointer_check == pointer_checksum(&*self.data, &*self.key)
+    }
+
 
-        Self { data, key }
+    /// Returns the size of the masked value in bytes, which is `size_of::<T>()`.
+    ///
+    /// This is non-secret metadata and does not touch the masked contents.
+    pub fn len_bytes(&self) -> usize {
+        size_of::<T>()
+    }
+
+    /// Returns whether the masked value is zero-sized, i.e. there is nothing to mask.
+    pub fn is_empty(&self) -> bool {
+        size_of::<T>() == 0
     }
 
     /// Rekeys the box, preserving its contents.
     pub fn rekey(&mut self) {
-        let mut diff_key = MaybeUninit::<T>::uninit();
-        getrandom::fill_uninit(diff_key.as_bytes_mut()).expect("no keygen");
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Rekey, size_of::<T>());
+        with_diff_scratch(|diff_key: &mut MaybeUninit<T>| {
+            keygen::fill(diff_key.as_bytes_mut());
 
-        unsafe {
-            xor_chunks::<T>(
-                Box::as_mut_ptr(&mut self.data).cast::<u8>(),
-                diff_key.as_ptr().cast::<u8>(),
-            );
-            xor_chunks::<T>(
-                self.key.as_mut_ptr().cast::<u8>(),
-                diff_key.as_ptr().cast::<u8>(),
-            );
-        }
+            unsafe {
+                xor_chunks::<T>(
+                    Box::as_mut_ptr(&mut self.data).cast::<u8>(),
+                    diff_key.as_ptr().cast::<u8>(),
+                );
+                xor_chunks::<T>(
+                    Box::as_mut_ptr(&mut self.key).cast::<u8>(),
+                    diff_key.as_ptr().cast::<u8>(),
+                );
+                // All bytes of the key are initialized (type invariant).
+                keygen::audit_key(&*self.key);
+            }
+        })
     }
 
     pub(crate) fn with_mangled<F, R>(&mut self, f: F) -> R
@@ -89,8 +274,10 @@ impl<T> MangledBoxArbitrary<T> {
     where
         F: FnOnce(NonNull<T>) -> R,
     {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
         let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
-        let key_ptr = self.key.as_ptr().cast::<u8>();
+        let key_ptr = Box::as_ptr(&self.key).cast::<u8>();
 
         // Never panics as that's a pointer into Box allocation.
         // Compiler is probably able to optimize this check out.
@@ -101,33 +288,19 @@ impl<T> MangledBoxArbitrary<T> {
         // 2. [`data_ptr`] and [`key_ptr`] point to an allocation of at least
         //    `size_of::<T>()` bytes because they are obtained from references
         //    to `MaybeUninit<T>`.
-        // 3. [`data_ptr`] points to heap allocation and [`key_ptr`] to
-        //    stack, therefore they do not overlap.
+        // 3. [`data_ptr`] and [`key_ptr`] point to two distinct heap
+        //    allocations, therefore they do not overlap.
         unsafe {
             xor_chunks::<T>(data_ptr, key_ptr);
         }
 
-        /// Structure that handles remangling the pointed-to memory when
-        /// dropped (both upon panic and successful [`with_unmangled`]
-        /// completion). It is scoped because it is unsafe to construct.
-        struct RemangleGuard<T> {
-            data: *mut u8,
-            key: *const u8,
-            token: PhantomData<T>,
-        }
-        impl<T> Drop for RemangleGuard<T> {
-            fn drop(&mut self) {
-                unsafe { xor_chunks::<T>(self.data, self.key) }
-            }
-        }
-
         // # Safety
         // 1. Both pointers point to some `MaybeUninit<T>`, so aligned
         // 2. [`data_ptr`] and [`key_ptr`] point to an allocation of at least
         //    `size_of::<T>()` bytes because they are obtained from references
         //    to `MaybeUninit<T>`.
-        // 3. [`data_ptr`] points to heap allocation and [`key_ptr`] to
-        //    stack, therefore they do not overlap.
+        // 3. [`data_ptr`] and [`key_ptr`] point to two distinct heap
+        //    allocations, therefore they do not overlap.
         let _guard = RemangleGuard::<T> {
             data: data_ptr,
             key: key_ptr,
@@ -137,15 +310,242 @@ impl<T> MangledBoxArbitrary<T> {
         f(data_nn.cast())
     }
 
+    /// Unmangles `len` bytes of the contents starting at `offset` and invokes the provided
+    /// closure on a pointer to them; the rest of the box stays mangled. Whether the closure
+    /// panics or returns normally, the range is remangled.
+    ///
+    /// This narrows the exposure to the field actually being touched, which matters for large
+    /// structures such as key schedules. The caller is responsible for the range covering
+    /// something meaningful to access at `u8` level (typically a whole field).
+    ///
+    /// # Panics
+    /// Panics if `offset + len` exceeds `size_of::<T>()`.
+    pub fn with_unmangled_range<F, R>(&mut self, offset: usize, len: usize, f: F) -> R
+    where
+        F: FnOnce(NonNull<u8>) -> R,
+    {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+
+        // # Safety
+        // The guard is dropped before returning, while `self` is still borrowed.
+        let (data_nn, _guard) = unsafe { self.unmangle_range(offset, len) };
+        f(data_nn)
+    }
+
+    /// Unmangles two disjoint byte ranges of the contents and invokes the provided closure
+    /// on pointers to them, e.g. to hand two fields to different subsystems at once; the
+    /// rest of the box stays mangled. Whether the closure panics or returns normally, both
+    /// ranges are remangled.
+    ///
+    /// # Panics
+    /// Panics if either range is out of bounds of `size_of::<T>()` or decreasing, or if the
+    /// ranges overlap; the ranges must be disjoint for the two pointers not to alias.
+    pub fn with_two_ranges<F, R>(&mut self, r1: Range<usize>, r2: Range<usize>, f: F) -> R
+    where
+        F: FnOnce(NonNull<u8>, NonNull<u8>) -> R,
+    {
+        for r in [&r1, &r2] {
+            assert!(
+                r.start <= r.end && r.end <= size_of::<T>(),
+                "range {r:?} is out of bounds of {}-byte contents",
+                size_of::<T>()
+            );
+        }
+        assert!(
+            r1.is_empty() || r2.is_empty() || r1.end <= r2.start || r2.end <= r1.start,
+            "ranges {r1:?} and {r2:?} overlap"
+        );
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+
+        // # Safety
+        // The guards are dropped before returning, while `self` is still borrowed; the
+        // ranges are disjoint, so the two unmanglings touch distinct bytes.
+        let (first, _first_guard) = unsafe { self.unmangle_range(r1.start, r1.len()) };
+        let (second, _second_guard) = unsafe { self.unmangle_range(r2.start, r2.len()) };
+        f(first, second)
+    }
+
+    /// Unmangles `len` bytes of the contents starting at `offset`, returning a pointer
+    /// to them and a guard remangling them when dropped.
+    ///
+    /// # Panics
+    /// Panics if `offset + len` exceeds `size_of::<T>()`.
+    ///
+    /// # Safety
+    /// The guard must be dropped before the box is accessed otherwise or dropped, and
+    /// ranges unmangled by guards alive at the same time must be disjoint.
+    unsafe fn unmangle_range(&mut self, offset: usize, len: usize) -> (NonNull<u8>, RemangleRangeGuard) {
+        assert!(
+            offset.checked_add(len).is_some_and(|end| end <= size_of::<T>()),
+            "range {offset}+{len} is out of bounds of {}-byte contents",
+            size_of::<T>()
+        );
+
+        // # Safety
+        // The assertion above guarantees that both offsets stay within (or one past the end
+        // of) their `MaybeUninit<T>` allocations.
+        let data_ptr = unsafe { Box::as_mut_ptr(&mut self.data).cast::<u8>().add(offset) };
+        let key_ptr = unsafe { Box::as_ptr(&self.key).cast::<u8>().add(offset) };
+
+        // Never panics as that's a pointer into Box allocation.
+        let data_nn: NonNull<u8> = NonNull::new(data_ptr).unwrap();
+
+        // # Safety
+        // 1. [`data_ptr`] and [`key_ptr`] point to at least `len` bytes, as checked above.
+        // 2. [`data_ptr`] and [`key_ptr`] point to two distinct heap
+        //    allocations, therefore they do not overlap.
+        unsafe {
+            xor_range(data_ptr, key_ptr, len);
+        }
+
+        // # Safety
+        // Same as for the unmangling above.
+        let guard = RemangleRangeGuard {
+            data: data_ptr,
+            key: key_ptr,
+            len,
+        };
+
+        (data_nn, guard)
+    }
+
+    /// Replaces the contents with a value constructed in place by the provided closure.
+    ///
+    /// The old masked bytes are zeroed and the key is redrawn before the closure runs, so
+    /// the old value is destroyed before any byte of the new one is written, and the two
+    /// never coexist unmasked. The closure receives a pointer to zeroed memory; whether it
+    /// panics or returns normally, the contents are masked with the fresh key afterwards.
+    ///
+    /// The old value's destructor is not run: if the box holds a value, destroy it with
+    /// [`Self::drop_in_place`] first.
+    pub fn overwrite_by_ptr<F>(&mut self, f: F)
+    where
+        F: FnOnce(NonNull<T>),
+    {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+        self.mark_initialized();
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+
+        // # Safety
+        // [`data_ptr`] is aligned and points to `size_of::<T>()` bytes; passing it
+        // twice zeroes them.
+        unsafe {
+            xor_chunks::<T>(data_ptr, data_ptr);
+        }
+        keygen::fill(self.key.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key`] is fully initialized
+        // # Safety
+        // [`key`] is fully initialized, see above.
+        unsafe { keygen::audit_key(&*self.key) };
+
+        let data_nn: NonNull<u8> = NonNull::new(data_ptr).unwrap();
+
+        // # Safety
+        // Same as in [`Self::with_unmangled`].
+        let _guard = RemangleGuard::<T> {
+            data: data_ptr,
+            key: Box::as_ptr(&self.key).cast::<u8>(),
+            token: PhantomData,
+        };
+
+        f(data_nn.cast())
+    }
+
     /// Drops the contents of the box, leaving it logically uninitialized.
     ///
     /// Using this is required to run any internal destructors, because the
     /// Drop implementation cannot know if there is any value to destroy.
     ///
+    /// For a box that owns its value for its whole life, [`crate::MangledOption`] runs the
+    /// destructor automatically and needs no `unsafe`.
+    ///
     /// # Safety
     /// [`Self::with_unmangled`] must have initialized the contents.
+    ///
+    /// # Panics
+    /// In debug builds, panics unless the contents were marked initialized (see
+    /// [`Self::mark_initialized`]) since the box was constructed or since the last call to
+    /// this, which catches dropping contents that were never written, e.g. only read, and
+    /// double drops.
     pub unsafe fn drop_in_place(&mut self) {
+        #[cfg(debug_assertions)]
+        assert!(self.initialized, "drop_in_place on contents that were never initialized or already dropped");
         self.with_unmangled(|p| unsafe { p.drop_in_place() });
+        #[cfg(debug_assertions)]
+        {
+            self.initialized = false;
+        }
+    }
+
+    /// Drops the contents of the box; an alias of [`Self::drop_in_place`] named after
+    /// [`MaybeUninit::assume_init_drop`].
+    ///
+    /// # Safety
+    /// Same as [`Self::drop_in_place`].
+    pub unsafe fn assume_init_drop(&mut self) {
+        unsafe { self.drop_in_place() }
+    }
+
+    /// Records that the contents are initialized, for the debug check in
+    /// [`Self::drop_in_place`]. [`Self::overwrite_by_ptr`], the box returned by
+    /// [`Self::try_map_into`] and the conversion from a [`MangledBox`] do this already;
+    /// call it after initializing the contents through [`Self::with_unmangled`] or by
+    /// other means. A no-op in release builds.
+    pub fn mark_initialized(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            self.initialized = true;
+        }
+    }
+
+    /// Unmangles the contents and invokes the provided closure on them as a pinned
+    /// reference, e.g. for self-referential contents. Whether the closure panics or returns
+    /// normally, the contents are remangled.
+    ///
+    /// The contents never move (see "Address stability" in the type documentation), but
+    /// the box cannot uphold the rest of the [`Pin`] contract by itself, hence the
+    /// requirements below.
+    ///
+    /// # Safety
+    /// - [`Self::with_unmangled`] must have initialized the contents
+    /// - once pinned, the contents must not be moved out through the other methods, and
+    ///   must be dropped with [`Self::drop_in_place`] before the box is dropped, as the
+    ///   box's own [`Drop`] does not run their destructor
+    pub unsafe fn with_unmangled_pinned<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(Pin<&mut T>) -> R,
+    {
+        // # Safety
+        // The contents are initialized and stay in place until dropped, as the caller
+        // guarantees; the reference does not outlive the closure call.
+        self.with_unmangled(|mut p| f(unsafe { Pin::new_unchecked(p.as_mut()) }))
+    }
+
+    /// Derives a new masked `U` from the contents by a fallible transform: `f` gets the
+    /// unmangled contents and a pointer to the unmangled contents of a fresh box, which it
+    /// should initialize on success. The new box is returned if `f` returns `Ok`.
+    ///
+    /// Both boxes are remangled whether `f` returns or panics, the new one first. On `Err`
+    /// or panic, the new box is then discarded without running `U`'s destructor, as its
+    /// contents may be partially initialized; anything `f` wrote into it is wiped, but
+    /// resources owned by a partially written `U` are leaked. The source box is untouched.
+    ///
+    /// # Safety
+    /// [`Self::with_unmangled`] must have initialized the contents.
+    pub unsafe fn try_map_into<U, E, F>(&mut self, f: F) -> Result<MangledBoxArbitrary<U>, E>
+    where
+        F: FnOnce(&T, NonNull<U>) -> Result<(), E>,
+    {
+        let mut target = MangledBoxArbitrary::<U>::new();
+        // # Safety
+        // The contents are initialized, as the caller guarantees, and the reference does not
+        // outlive the closure call, which ends before the remangling.
+        self.with_unmangled(|src| target.with_unmangled(|dst| f(unsafe { src.as_ref() }, dst)))?;
+        target.mark_initialized();
+        Ok(target)
     }
 }
 
@@ -155,28 +555,100 @@ impl<T> Default for MangledBoxArbitrary<T> {
     }
 }
 
-impl<T> Drop for MangledBoxArbitrary<T> {
+/// Adopts the masked bytes of a [`MangledBox`] as they are, without unmasking them;
+/// a [`NoUninit`] value needs no destructor, so nothing is lost.
+impl<T: NoUninit, A: Allocator> From<MangledBox<T, A>> for MangledBoxArbitrary<T, A> {
+    fn from(value: MangledBox<T, A>) -> Self {
+        let (data, key) = value.into_parts();
+        Self {
+            pointer_check: pointer_checksum(&*data, &*key),
+            data,
+            key,
+            #[cfg(debug_assertions)]
+            initialized: true,
+        }
+    }
+}
+
+/// Adopts the masked bytes as they are, without unmasking them. The masked data and the
+/// key are fully initialized, as the XOR intrinsic writes every byte, so the contents
+/// satisfy the [`MangledBox`] invariant whatever was (or was not) written to the box.
+impl<T: NoUninit, A: Allocator> From<MangledBoxArbitrary<T, A>> for MangledBox<T, A> {
+    fn from(value: MangledBoxArbitrary<T, A>) -> Self {
+        let this = std::mem::ManuallyDrop::new(value);
+        // # Safety
+        // 1. [`this`] is never used or dropped again, so each box is moved out exactly once
+        // 2. See above for initialization
+        unsafe {
+            let data = std::ptr::read(&this.data);
+            let key = std::ptr::read(&this.key);
+            MangledBox::from_parts(data, key)
+        }
+    }
+}
+
+// See "Unwind safety" in the type documentation.
+impl<T, A: Allocator> UnwindSafe for MangledBoxArbitrary<T, A> {}
+impl<T, A: Allocator> RefUnwindSafe for MangledBoxArbitrary<T, A> {}
+
+impl<T: Pod, A: Allocator> SecretContainer for MangledBoxArbitrary<T, A> {
+    type Value = T;
+
+    fn rekey(&mut self) {
+        MangledBoxArbitrary::rekey(self);
+    }
+
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        // # Safety
+        // The data starts zeroed and is only ever XORed with initialized key bytes or
+        // overwritten with a `T: Pod`, which has no padding; so all bytes are initialized,
+        // and any initialized bit pattern is a valid `T`.
+        Some(self.with_unmangled(|mut p| f(unsafe { p.as_mut() })))
+    }
+}
+
+impl<T, A: Allocator> Drop for MangledBoxArbitrary<T, A> {
     fn drop(&mut self) {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Drop, size_of::<T>());
+        keygen::audit_forget(&*self.key);
         let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
-        let key_ptr = self.key.as_mut_ptr().cast::<u8>();
+        let key_ptr = Box::as_mut_ptr(&mut self.key).cast::<u8>();
 
         // # Safety
         // 1. Both pointers point to some `MaybeUninit<T>`, so aligned
         // 2. Both pointers were obtained from `&mut MaybeUninit<T>`
         //    to an allocation of at least `size_of::<T>()`.
         // 3. Each call passes the same pointer in both arguments.
+        #[cfg(not(feature = "sentinel-wipe"))]
         unsafe {
             xor_chunks::<T>(data_ptr, data_ptr);
             xor_chunks::<T>(key_ptr, key_ptr);
         }
+
+        // # Safety
+        // Both pointers are valid for writes of `size_of::<T>()` bytes, see above; writing
+        // needs no initialized bytes.
+        #[cfg(feature = "sentinel-wipe")]
+        unsafe {
+            crate::sentinel::fill(data_ptr, size_of::<T>());
+            crate::sentinel::fill(key_ptr, size_of::<T>());
+        }
     }
 }
 
 #[cfg(all(test, not(miri)))]
 mod tests {
+    use std::alloc::{AllocError, Allocator, Global, Layout};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
     use std::clone::CloneToUninit;
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
+    use std::marker::PhantomPinned;
+    use std::num::NonZeroU32;
+    use std::pin::Pin;
     use std::ptr::NonNull;
+    use std::panic::{RefUnwindSafe, UnwindSafe};
     use std::rc::Rc;
 
     use super::MangledBoxArbitrary as MangledBox;
@@ -189,6 +661,7 @@ mod tests {
         let mut empty_box = MangledBox::<()>::new();
         ensure_send(&empty_box);
         ensure_sync(&empty_box);
+        assert!(empty_box.is_empty());
 
         empty_box.with_unmangled(|_| {});
     }
@@ -212,6 +685,25 @@ mod tests {
         });
     }
 
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    #[repr(C, align(128))]
+    struct Big([u64; 4]);
+
+    #[test]
+    fn overaligned_non_zst() {
+        for _ in 0..8 {
+            let mut big_box = MangledBox::<Big>::new();
+            assert_eq!(Box::as_ptr(&big_box.key).align_offset(128), 0, "key under-aligned");
+
+            big_box.with_unmangled(|p| {
+                assert_eq!(p.as_ptr().align_offset(128), 0, "data under-aligned");
+                unsafe { p.write(Big([1, 2, 3, 4])) };
+            });
+            big_box.rekey();
+            big_box.with_unmangled(|p| assert_eq!(unsafe { p.read() }, Big([1, 2, 3, 4])));
+        }
+    }
+
     struct ReportDrop(Rc<RefCell<bool>>);
     impl Drop for ReportDrop {
         fn drop(&mut self) {
@@ -239,6 +731,7 @@ mod tests {
                 // that [`place`] is initialized after closure exits.
                 unsafe { drop_reported_clone.clone_to_uninit(place) };
             });
+            box_.mark_initialized();
             assert!(!*drop_reported.borrow(), "dropped a live box");
 
             unsafe {
@@ -286,6 +779,7 @@ mod tests {
         box_.with_unmangled(|p| unsafe {
             p.write("hello".to_owned());
         });
+        box_.mark_initialized();
         box_.with_unmangled(|mut p| {
             assert_eq!(unsafe { p.as_ref() }, "hello");
             unsafe {
@@ -302,4 +796,333 @@ mod tests {
             box_.drop_in_place();
         }
     }
+
+    /// Allocator counting its live allocations.
+    struct CountingAlloc(AtomicUsize);
+
+    unsafe impl Allocator for &CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn custom_allocator() {
+        let alloc = CountingAlloc(AtomicUsize::new(0));
+        {
+            let mut box_ = MangledBox::<String, _>::new_in(&alloc);
+            assert_eq!(alloc.0.load(Ordering::SeqCst), 2);
+
+            box_.with_unmangled(|p| unsafe { p.write("in arena".to_owned()) });
+            box_.mark_initialized();
+            box_.rekey();
+            box_.with_unmangled(|p| {
+                assert_eq!(unsafe { p.as_ref() }, "in arena");
+            });
+            unsafe {
+                box_.drop_in_place();
+            }
+        }
+        assert_eq!(alloc.0.load(Ordering::SeqCst), 0, "data or key allocation leaked");
+    }
+
+    #[test]
+    fn panic_remangles() {
+        const PATTERN: [u8; 32] = [0x5A; 32];
+
+        let mut box_ = MangledBox::<[u8; 32]>::new();
+        box_.with_unmangled(|p| unsafe { p.write(PATTERN) });
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            box_.with_unmangled(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+
+        let masked = box_.with_mangled(|p| unsafe { p.read() });
+        assert_ne!(masked, PATTERN, "contents left unmasked after panic");
+        box_.with_unmangled(|p| {
+            assert_eq!(unsafe { p.read() }, PATTERN);
+        });
+    }
+
+    #[test]
+    fn unwind_safe_regardless_of_contents() {
+        fn ensure_unwind_safe<T: UnwindSafe + RefUnwindSafe>(_v: &T) {}
+
+        // `Cell` is not `RefUnwindSafe`, so the box would not be either if derived.
+        let mut box_ = MangledBox::<Cell<u32>>::new();
+        ensure_unwind_safe(&box_);
+        box_.with_unmangled(|p| unsafe { p.write(Cell::new(7)) });
+
+        let shared = &box_;
+        assert!(catch_unwind(|| ensure_unwind_safe(shared)).is_ok());
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            box_.with_unmangled(|p| {
+                unsafe { p.as_ref() }.set(8);
+                panic!("closure failed");
+            });
+        }));
+        assert!(result.is_err());
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.as_ref() }.get(), 8));
+    }
+
+    #[test]
+    fn overwrite_by_ptr() {
+        let mut box_ = MangledBox::<String>::new();
+        box_.with_unmangled(|p| unsafe { p.write("old secret".to_owned()) });
+        box_.mark_initialized();
+        unsafe {
+            box_.drop_in_place();
+        }
+
+        box_.overwrite_by_ptr(|p| unsafe { p.write("new secret".to_owned()) });
+        box_.with_unmangled(|p| {
+            assert_eq!(unsafe { p.as_ref() }, "new secret");
+        });
+        unsafe {
+            box_.drop_in_place();
+        }
+    }
+
+    #[test]
+    fn range_unmangling() {
+        #[repr(C)]
+        struct Pair {
+            a: u64,
+            b: u64,
+        }
+
+        let mut box_ = MangledBox::<Pair>::new();
+        box_.with_unmangled(|p| unsafe { p.write(Pair { a: 0x1111, b: 0x2222 }) });
+
+        box_.with_unmangled_range(8, 8, |p| {
+            let b = p.cast::<u64>();
+            let a = unsafe { b.sub(1) };
+            assert_eq!(unsafe { b.read() }, 0x2222);
+            assert_ne!(unsafe { a.read() }, 0x1111, "field outside the range was unmangled");
+            unsafe { b.write(0x3333) };
+        });
+
+        box_.with_unmangled(|p| {
+            let pair = unsafe { p.as_ref() };
+            assert_eq!(pair.a, 0x1111);
+            assert_eq!(pair.b, 0x3333);
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn range_out_of_bounds() {
+        let mut box_ = MangledBox::<u64>::new();
+        box_.with_unmangled_range(4, 8, |_| {});
+    }
+
+    #[test]
+    fn new_checked_accepts_frozen() {
+        let mut box_ = MangledBox::<(u64, Rc<Cell<u8>>)>::new_checked();
+        let shared = Rc::new(Cell::new(1));
+        box_.with_unmangled(|p| unsafe { p.write((7, Rc::clone(&shared))) });
+        box_.mark_initialized();
+        box_.with_unmangled(|p| unsafe { p.as_ref() }.1.set(2));
+        assert_eq!(shared.get(), 2);
+        unsafe { box_.drop_in_place() };
+        assert_eq!(Rc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn convert_between_flavors() {
+        let mut plain = crate::MangledBox::<[u8; 32]>::new();
+        plain.with_unmangled(|p| unsafe { p.write([9; 32]) });
+
+        let mut arbitrary = MangledBox::from(plain);
+        arbitrary.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [9; 32]));
+        arbitrary.rekey();
+
+        let mut plain = crate::MangledBox::from(arbitrary);
+        plain.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [9; 32]));
+    }
+
+    #[test]
+    fn two_ranges() {
+        #[repr(C)]
+        struct Triple {
+            a: u32,
+            b: u32,
+            c: u32,
+        }
+
+        let mut box_ = MangledBox::<Triple>::new();
+        box_.with_unmangled(|p| unsafe { p.write(Triple { a: 1, b: 2, c: 3 }) });
+
+        box_.with_two_ranges(8..12, 0..4, |c, a| {
+            let (a, c) = (a.cast::<u32>(), c.cast::<u32>());
+            assert_eq!(unsafe { (a.read(), c.read()) }, (1, 3));
+            assert_ne!(unsafe { a.add(1).read() }, 2, "field outside the ranges was unmangled");
+            unsafe {
+                a.write(10);
+                c.write(30);
+            }
+        });
+
+        box_.with_unmangled(|p| {
+            let triple = unsafe { p.as_ref() };
+            assert_eq!((triple.a, triple.b, triple.c), (10, 2, 30));
+        });
+    }
+
+    #[test]
+    fn try_map_into() {
+        let mut source = MangledBox::<String>::new();
+        source.with_unmangled(|p| unsafe { p.write(String::from("4096")) });
+        source.mark_initialized();
+
+        let parse = |s: &String, dst: NonNull<u64>| s.parse().map(|v| unsafe { dst.write(v) });
+        let mut parsed = unsafe { source.try_map_into(parse) }.unwrap();
+        assert_eq!(parsed.with_unmangled(|p| unsafe { p.read() }), 4096);
+
+        source.with_unmangled(|mut p| unsafe { p.as_mut().push('x') });
+        assert!(unsafe { source.try_map_into(parse) }.is_err());
+        let failed = unsafe {
+            source.try_map_into(|_, dst: NonNull<String>| {
+                dst.write(String::from("never dropped"));
+                Err(())
+            })
+        };
+        assert!(failed.is_err());
+        source.with_unmangled(|p| assert_eq!(unsafe { p.as_ref() }, "4096x"));
+        unsafe { source.drop_in_place() };
+    }
+
+    #[test]
+    fn self_referential_contents() {
+        struct SelfRef {
+            value: u64,
+            this: *const u64,
+            _pin: PhantomPinned,
+        }
+
+        let mut box_ = MangledBox::<SelfRef>::new();
+        box_.with_unmangled(|p| unsafe {
+            let value = &raw const (*p.as_ptr()).value;
+            p.write(SelfRef { value: 7, this: value, _pin: PhantomPinned });
+        });
+        box_.mark_initialized();
+
+        let mut moved = vec![box_];
+        let mut box_ = moved.pop().unwrap();
+        let read = |s: Pin<&mut SelfRef>| unsafe { *s.this };
+        assert_eq!(unsafe { box_.with_unmangled_pinned(read) }, 7);
+        unsafe { box_.drop_in_place() };
+    }
+
+    #[test]
+    #[should_panic(expected = "overlap")]
+    fn two_ranges_overlapping() {
+        let mut box_ = MangledBox::<u64>::new();
+        box_.with_two_ranges(0..4, 3..8, |_, _| {});
+    }
+
+    #[test]
+    fn niche_enums() {
+        let mut some = MangledBox::<Option<NonZeroU32>>::new();
+        some.with_unmangled(|p| unsafe { p.write(NonZeroU32::new(7)) });
+        some.rekey();
+        some.with_unmangled(|p| assert_eq!(unsafe { p.read() }, NonZeroU32::new(7)));
+
+        let mut none = MangledBox::<Option<NonZeroU32>>::new();
+        none.with_unmangled(|p| unsafe { p.write(None) });
+        none.rekey();
+        none.with_unmangled(|p| assert_eq!(unsafe { p.read() }, None));
+    }
+
+    #[test]
+    fn masked_discriminant_need_not_be_valid() {
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        #[repr(u8)]
+        enum Level {
+            Low = 1,
+            High = 2,
+        }
+
+        let mut masked_invalid = false;
+        for level in [Level::Low, Level::High].into_iter().cycle().take(16) {
+            let mut box_ = MangledBox::<Level>::new();
+            box_.with_unmangled(|p| unsafe { p.write(level) });
+            // The masked byte is the discriminant XOR a random key byte, so over 16 boxes it
+            // is outside `1..=2` at least once except with negligible probability.
+            let masked = unsafe { box_.data.as_bytes()[0].assume_init() };
+            masked_invalid |= !(1..=2).contains(&masked);
+            box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, level));
+        }
+        assert!(masked_invalid);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "never initialized")]
+    fn drop_in_place_uninitialized() {
+        let mut box_ = MangledBox::<String>::new();
+        unsafe { box_.drop_in_place() };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "never initialized")]
+    fn drop_in_place_after_read_only_access() {
+        let mut box_ = MangledBox::<String>::new();
+        box_.with_unmangled(|_| ());
+        unsafe { box_.drop_in_place() };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already dropped")]
+    fn double_assume_init_drop() {
+        let mut box_ = MangledBox::<String>::new();
+        box_.with_unmangled(|p| unsafe { p.write(String::from("secret")) });
+        box_.mark_initialized();
+        unsafe { box_.assume_init_drop() };
+        unsafe { box_.assume_init_drop() };
+    }
+
+    #[test]
+    fn one_mebibyte_rekey() {
+        const LEN: usize = 1 << 20;
+
+        // Nothing `LEN`-sized may land on the stack, which is smaller than the secret.
+        std::thread::Builder::new()
+            .stack_size(LEN / 2)
+            .spawn(|| {
+                let mut box_ = MangledBox::<[u8; LEN]>::new();
+                box_.with_unmangled(|p| unsafe { p.cast::<u8>().write_bytes(0x5a, LEN) });
+                box_.rekey();
+                box_.with_unmangled(|p| {
+                    let bytes = unsafe { std::slice::from_raw_parts(p.as_ptr().cast::<u8>(), LEN) };
+                    assert!(bytes.iter().all(|&b| b == 0x5a));
+                });
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn pointer_checksum() {
+        let mut box_ = MangledBox::<u64>::new();
+        assert!(box_.pointers_intact());
+        let converted = MangledBox::from(crate::MangledBox::<u64>::new());
+        assert!(converted.pointers_intact());
+
+        // A payload whose bytes were not written as a whole fails the check.
+        box_.pointer_check ^= 1 << 12;
+        assert!(!box_.pointers_intact());
+        box_.pointer_check ^= 1 << 12;
+    }
 }
@@ -4,6 +4,31 @@
 //! compile- nor runtime) so we have to mask all of them.
 //!
 //! That necessitates assembly code.
+//!
+//! Register operands are always passed as 64-bit values, zero-extended from `usize`,
+//! so the assembly does not assume 64-bit pointers: on ILP32 targets such as arm64_32
+//! (or x86_64's x32) the upper half of a pointer-sized register is otherwise undefined.
+//! It also means the loop counters cannot overflow: a length fits in an allocation, so it
+//! is at most `isize::MAX`, and the block loops look at most 32 bytes past the index
+//! before comparing. A zero length accesses no memory at all.
+//!
+//! On x86_64 the implementation is picked at compile time from the statically enabled
+//! target features: AVX2 (e.g. with `-C target-cpu=native` on a recent CPU) XORs 32 bytes
+//! at a time, SSE2 (part of the x86_64 baseline, so the default) 16 bytes at a time, and
+//! a scalar loop is used when neither is enabled. Each handles the remaining tail bytes
+//! one by one. There is no runtime CPU detection: a binary only ever contains the variant
+//! its target features guarantee to be available, with no dispatch branch.
+//!
+//! Types of 1, 2, 4, 8 or 16 bytes skip the loops altogether: as the size is known at
+//! compile time, [`xor_chunks_intrinsic_baseline`] XORs them with a single load and XOR of
+//! one or two general-purpose registers.
+
+/// Zero-extends a pointer's address to a full 64-bit register operand, exposing its
+/// provenance to the assembly which accesses memory through it.
+#[inline(always)]
+fn reg_ptr<P>(p: *const P) -> u64 {
+    p.expose_provenance() as u64
+}
 
 /// XORs the data behind the first pointer using the key from the second pointer
 /// in a fashion that does not provide ordering guarantees but is guaranteed
@@ -16,13 +41,9 @@
 ///
 /// No requirements on initialization status are made.
 /// Garbage in, garbage out (instead of UB out).
-#[cfg(target_arch = "x86_64")]
+#[inline]
 pub unsafe fn xor_chunks_intrinsic_baseline<T>(data: *mut u8, key: *const u8) {
-    use std::arch::asm;
-
-    let size = std::mem::size_of::<T>();
-    let min_alignment = std::mem::align_of::<T>();
-    let min_alignment_bits: u32 = min_alignment.trailing_zeros();
+    let min_alignment_bits: u32 = std::mem::align_of::<T>().trailing_zeros();
 
     let co_aligned_bits = data
         .addr()
@@ -33,7 +54,148 @@ pub unsafe fn xor_chunks_intrinsic_baseline<T>(data: *mut u8, key: *const u8) {
         "first safety precondition: data and key must be aligned for T"
     );
 
-    let index = 0usize;
+    if unsafe { xor_register_sized(data, key, std::mem::size_of::<T>()) } {
+        return;
+    }
+    unsafe { xor_chunks_len(data, key, std::mem::size_of::<T>()) }
+}
+
+/// XORs `size` bytes like [`xor_chunks_len`] if `size` is 1, 2, 4, 8 or 16, using one or
+/// two general-purpose registers and no loop, and returns whether it did. Always inlined,
+/// so that for a constant `size` only the matching arm (or nothing) is left.
+///
+/// # Safety
+/// - `data` and `key` must have at least `size` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+unsafe fn xor_register_sized(data: *mut u8, key: *const u8, size: usize) -> bool {
+    use std::arch::asm;
+
+    let data = reg_ptr(data);
+    let key = reg_ptr(key);
+    unsafe {
+        match size {
+            1 => asm!(
+                "mov {k:l}, byte ptr [{key}]",
+                "xor byte ptr [{data}], {k:l}",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _,
+                options(nostack),
+            ),
+            2 => asm!(
+                "mov {k:x}, word ptr [{key}]",
+                "xor word ptr [{data}], {k:x}",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _,
+                options(nostack),
+            ),
+            4 => asm!(
+                "mov {k:e}, dword ptr [{key}]",
+                "xor dword ptr [{data}], {k:e}",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _,
+                options(nostack),
+            ),
+            8 => asm!(
+                "mov {k}, qword ptr [{key}]",
+                "xor qword ptr [{data}], {k}",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _,
+                options(nostack),
+            ),
+            16 => asm!(
+                "mov {k}, qword ptr [{key}]",
+                "xor qword ptr [{data}], {k}",
+                "mov {k}, qword ptr [{key} + 8]",
+                "xor qword ptr [{data} + 8], {k}",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _,
+                options(nostack),
+            ),
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// XORs `size` bytes like [`xor_chunks_len`] if `size` is 1, 2, 4, 8 or 16, using one or
+/// two general-purpose registers and no loop, and returns whether it did. Always inlined,
+/// so that for a constant `size` only the matching arm (or nothing) is left.
+///
+/// Both halves of a 16-byte value are loaded before either is stored, so that `data` and
+/// `key` may be the same.
+///
+/// # Safety
+/// - `data` and `key` must have at least `size` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+unsafe fn xor_register_sized(data: *mut u8, key: *const u8, size: usize) -> bool {
+    use std::arch::asm;
+
+    let data = reg_ptr(data);
+    let key = reg_ptr(key);
+    unsafe {
+        match size {
+            1 => asm!(
+                "ldrb {k:w}, [{key}]",
+                "ldrb {d:w}, [{data}]",
+                "eor {d:w}, {d:w}, {k:w}",
+                "strb {d:w}, [{data}]",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _, d = out(reg) _,
+                options(nostack),
+            ),
+            2 => asm!(
+                "ldrh {k:w}, [{key}]",
+                "ldrh {d:w}, [{data}]",
+                "eor {d:w}, {d:w}, {k:w}",
+                "strh {d:w}, [{data}]",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _, d = out(reg) _,
+                options(nostack),
+            ),
+            4 => asm!(
+                "ldr {k:w}, [{key}]",
+                "ldr {d:w}, [{data}]",
+                "eor {d:w}, {d:w}, {k:w}",
+                "str {d:w}, [{data}]",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _, d = out(reg) _,
+                options(nostack),
+            ),
+            8 => asm!(
+                "ldr {k}, [{key}]",
+                "ldr {d}, [{data}]",
+                "eor {d}, {d}, {k}",
+                "str {d}, [{data}]",
+                data = in(reg) data, key = in(reg) key, k = out(reg) _, d = out(reg) _,
+                options(nostack),
+            ),
+            16 => asm!(
+                "ldp {k0}, {k1}, [{key}]",
+                "ldp {d0}, {d1}, [{data}]",
+                "eor {d0}, {d0}, {k0}",
+                "eor {d1}, {d1}, {k1}",
+                "stp {d0}, {d1}, [{data}]",
+                data = in(reg) data, key = in(reg) key,
+                k0 = out(reg) _, k1 = out(reg) _, d0 = out(reg) _, d1 = out(reg) _,
+                options(nostack),
+            ),
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// XORs `len` bytes behind the first pointer using the key from the second pointer,
+/// with the same guarantees as [`xor_chunks_intrinsic_baseline`] but with the length
+/// known only at runtime, so variable-length containers need no per-size instance.
+///
+/// # Safety
+/// - `data` and `key` must have at least `len` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+///
+/// No requirements on initialization status are made.
+/// Garbage in, garbage out (instead of UB out).
+#[cfg(all(target_arch = "x86_64", not(target_feature = "sse2")))]
+pub unsafe fn xor_chunks_len(data: *mut u8, key: *const u8, len: usize) {
+    use std::arch::asm;
+
+    let index = 0u64;
     unsafe {
         // TODO: consider wider-sized loads
         // TODO: consider partial loop unrolling
@@ -47,42 +209,126 @@ pub unsafe fn xor_chunks_intrinsic_baseline<T>(data: *mut u8, key: *const u8) {
                 "jmp 2b",
             "3:",
             index = inout(reg) index => _,
-            size = in(reg) size,
-            data = in(reg) data,
-            key = in(reg) key,
+            size = in(reg) len as u64,
+            data = in(reg) reg_ptr(data),
+            key = in(reg) reg_ptr(key),
             key_byte = out(reg_byte) _,
             options(nostack),
         );
     }
 }
 
-/// XORs the data behind the first pointer using the key from the second pointer
-/// in a fashion that does not provide ordering guarantees but is guaranteed
-/// not to be elided.
+/// XORs `len` bytes behind the first pointer using the key from the second pointer,
+/// with the same guarantees as [`xor_chunks_intrinsic_baseline`] but with the length
+/// known only at runtime, so variable-length containers need no per-size instance.
+///
+/// SSE2 variant: 16-byte blocks, then the tail byte by byte.
 ///
 /// # Safety
-/// - `data` and `key` must be correctly aligned for `T`
-/// - `data` and `key` must have at least `size_of::<T>()` bytes allocated
+/// - `data` and `key` must have at least `len` bytes allocated
 /// - `data` and `key` must either be non-overlapping or the same
 ///
 /// No requirements on initialization status are made.
 /// Garbage in, garbage out (instead of UB out).
-#[cfg(target_arch = "aarch64")]
-pub unsafe fn xor_chunks_intrinsic_baseline<T>(data: *mut u8, key: *const u8) {
+#[cfg(all(target_arch = "x86_64", target_feature = "sse2", not(target_feature = "avx2")))]
+pub unsafe fn xor_chunks_len(data: *mut u8, key: *const u8, len: usize) {
     use std::arch::asm;
 
-    let size = std::mem::size_of::<T>();
-    let min_alignment = std::mem::align_of::<T>();
-    let min_alignment_bits: u32 = min_alignment.trailing_zeros();
+    let index = 0u64;
+    unsafe {
+        asm!(
+            "2:",
+                "lea {next}, [{index} + 16]",
+                "cmp {next}, {size}",
+                "ja 3f",
+                "movdqu {key_block}, xmmword ptr [{key} + {index}]",
+                "movdqu {data_block}, xmmword ptr [{data} + {index}]",
+                "pxor {data_block}, {key_block}",
+                "movdqu xmmword ptr [{data} + {index}], {data_block}",
+                "mov {index}, {next}",
+                "jmp 2b",
+            "3:",
+                "cmp {index}, {size}",
+                "jae 4f",
+                "mov {key_byte}, byte ptr [{key} + {index}]",
+                "xor byte ptr [{data} + {index}], {key_byte}",
+                "add {index}, 1",
+                "jmp 3b",
+            "4:",
+            index = inout(reg) index => _,
+            next = out(reg) _,
+            size = in(reg) len as u64,
+            data = in(reg) reg_ptr(data),
+            key = in(reg) reg_ptr(key),
+            key_block = out(xmm_reg) _,
+            data_block = out(xmm_reg) _,
+            key_byte = out(reg_byte) _,
+            options(nostack),
+        );
+    }
+}
 
-    let co_aligned_bits = data
-        .addr()
-        .trailing_zeros()
-        .min(key.addr().trailing_zeros());
-    debug_assert!(
-        co_aligned_bits >= min_alignment_bits,
-        "first safety precondition: data and key must be aligned for T"
-    );
+/// XORs `len` bytes behind the first pointer using the key from the second pointer,
+/// with the same guarantees as [`xor_chunks_intrinsic_baseline`] but with the length
+/// known only at runtime, so variable-length containers need no per-size instance.
+///
+/// AVX2 variant: 32-byte blocks, then the tail byte by byte.
+///
+/// # Safety
+/// - `data` and `key` must have at least `len` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+///
+/// No requirements on initialization status are made.
+/// Garbage in, garbage out (instead of UB out).
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+pub unsafe fn xor_chunks_len(data: *mut u8, key: *const u8, len: usize) {
+    use std::arch::asm;
+
+    let index = 0u64;
+    unsafe {
+        asm!(
+            "2:",
+                "lea {next}, [{index} + 32]",
+                "cmp {next}, {size}",
+                "ja 3f",
+                "vmovdqu {key_block}, ymmword ptr [{key} + {index}]",
+                "vpxor {key_block}, {key_block}, ymmword ptr [{data} + {index}]",
+                "vmovdqu ymmword ptr [{data} + {index}], {key_block}",
+                "mov {index}, {next}",
+                "jmp 2b",
+            "3:",
+                "cmp {index}, {size}",
+                "jae 4f",
+                "mov {key_byte}, byte ptr [{key} + {index}]",
+                "xor byte ptr [{data} + {index}], {key_byte}",
+                "add {index}, 1",
+                "jmp 3b",
+            "4:",
+            index = inout(reg) index => _,
+            next = out(reg) _,
+            size = in(reg) len as u64,
+            data = in(reg) reg_ptr(data),
+            key = in(reg) reg_ptr(key),
+            key_block = out(ymm_reg) _,
+            key_byte = out(reg_byte) _,
+            options(nostack),
+        );
+    }
+}
+
+/// XORs `len` bytes behind the first pointer using the key from the second pointer,
+/// with the same guarantees as [`xor_chunks_intrinsic_baseline`] but with the length
+/// known only at runtime, so variable-length containers need no per-size instance.
+///
+/// # Safety
+/// - `data` and `key` must have at least `len` bytes allocated
+/// - `data` and `key` must either be non-overlapping or the same
+///
+/// No requirements on initialization status are made.
+/// Garbage in, garbage out (instead of UB out).
+#[cfg(target_arch = "aarch64")]
+pub unsafe fn xor_chunks_len(data: *mut u8, key: *const u8, len: usize) {
+    use std::arch::asm;
 
     unsafe {
         asm!(
@@ -97,9 +343,9 @@ pub unsafe fn xor_chunks_intrinsic_baseline<T>(data: *mut u8, key: *const u8) {
             "2:",
             key_byte = out(reg) _,
             tmp = out(reg) _,
-            size = in(reg) size,
-            data = in(reg) data,
-            key = in(reg) key,
+            size = inout(reg) len as u64 => _,
+            data = inout(reg) reg_ptr(data) => _,
+            key = inout(reg) reg_ptr(key) => _,
             options(nostack),
         );
     }
@@ -174,6 +420,8 @@ mod tests {
         test_xor_chunks_for_type::<u16>();
         test_xor_chunks_for_type::<u32>();
         test_xor_chunks_for_type::<u64>();
+        test_xor_chunks_for_type::<u128>();
+        test_xor_chunks_for_type::<[u8; 16]>();
         test_xor_chunks_for_type::<Foo>();
         test_xor_chunks_for_type::<Align16>();
         test_xor_chunks_for_type::<(u8, u32, (u16, u8, u16, u64))>();
@@ -260,7 +508,7 @@ mod tests {
             a: 0x12,
             b: 0x3456789A,
         };
-        let key = vec![0xFF, 0x00, 0x00, 0x00, 0xEE, 0xDD, 0xCC, 0xBB];
+        let key = [0xFF, 0x00, 0x00, 0x00, 0xEE, 0xDD, 0xCC, 0xBB];
         unsafe {
             xor_chunks_intrinsic_baseline::<Padded>((&raw mut data).cast::<u8>(), key.as_ptr());
         }
@@ -277,4 +525,103 @@ mod tests {
             }
         );
     }
+
+    /// Sizes covered by [`xor_dispatch`]: every small size, then the neighbourhoods
+    /// of typical block widths where wider loads and tail handling meet.
+    const DISPATCH_SIZES: [usize; 52] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23,
+        24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 47, 48, 49, 63, 64, 65, 95, 96, 97, 127, 128,
+        129, 191, 192, 193, 255, 256, 257,
+    ];
+
+    /// Runs [`xor_chunks_intrinsic_baseline`] on `[u8; len]` for a runtime `len`
+    /// taken from [`DISPATCH_SIZES`].
+    unsafe fn xor_dispatch(data: *mut u8, key: *const u8, len: usize) {
+        macro_rules! dispatch {
+            ($($n:literal)*) => {
+                match len {
+                    $($n => unsafe { xor_chunks_intrinsic_baseline::<[u8; $n]>(data, key) },)*
+                    _ => unreachable!("size {len} is not covered by dispatch"),
+                }
+            };
+        }
+        dispatch!(
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23
+            24 25 26 27 28 29 30 31 32 33 47 48 49 63 64 65 95 96 97 127 128
+            129 191 192 193 255 256 257
+        );
+    }
+
+    /// Checks `xor` against a byte-wise reference on random buffers, lengths drawn
+    /// by `pick_len` (at most 512) and offsets, then checks that a second call undoes it.
+    fn randomized_against_reference(
+        pick_len: impl Fn(&mut rand::rngs::StdRng) -> usize,
+        xor: unsafe fn(*mut u8, *const u8, usize),
+    ) {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        const SCRATCH: usize = 512;
+
+        let seed: u64 = rand::rng().random();
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        for _ in 0..2000 {
+            let len = pick_len(&mut rng);
+            let d = rng.random_range(0..=SCRATCH - len);
+            let k = rng.random_range(0..=SCRATCH - len);
+
+            let mut data = [0u8; SCRATCH];
+            let mut key = [0u8; SCRATCH];
+            rng.fill(&mut data[..]);
+            rng.fill(&mut key[..]);
+
+            let original = data;
+            let mut expected = data;
+            for i in 0..len {
+                expected[d + i] ^= key[k + i];
+            }
+
+            unsafe {
+                xor(data.as_mut_ptr().add(d), key.as_ptr().add(k), len);
+            }
+            assert_eq!(data, expected, "seed {seed}: len {len}, data at {d}, key at {k}");
+
+            unsafe {
+                xor(data.as_mut_ptr().add(d), key.as_ptr().add(k), len);
+            }
+            assert_eq!(data, original, "seed {seed}: len {len}, data at {d}, key at {k} (undo)");
+        }
+    }
+
+    #[test]
+    fn test_randomized_against_reference() {
+        use rand::Rng;
+        randomized_against_reference(
+            |rng| DISPATCH_SIZES[rng.random_range(0..DISPATCH_SIZES.len())],
+            xor_dispatch,
+        );
+    }
+
+    #[test]
+    fn test_len_boundaries() {
+        // Zero length must not touch memory, so even dangling pointers are fine.
+        let dangling = std::ptr::NonNull::<u8>::dangling().as_ptr();
+        unsafe { xor_chunks_len(dangling, dangling, 0) };
+
+        let mut data = [0x0Fu8, 0xAA, 0x55];
+        let key = [0xFFu8, 0xFF, 0xFF];
+        unsafe { xor_chunks_len(data.as_mut_ptr().add(1), key.as_ptr(), 0) };
+        assert_eq!(data, [0x0F, 0xAA, 0x55]);
+        unsafe { xor_chunks_len(data.as_mut_ptr().add(1), key.as_ptr(), 1) };
+        assert_eq!(data, [0x0F, 0x55, 0x55]);
+        unsafe { xor_chunks_len(data.as_mut_ptr(), key.as_ptr(), 1) };
+        assert_eq!(data, [0xF0, 0x55, 0x55]);
+    }
+
+    #[test]
+    fn test_len_randomized_against_reference() {
+        use rand::Rng;
+        randomized_against_reference(|rng| rng.random_range(0..=512), xor_chunks_len);
+    }
 }
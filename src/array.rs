@@ -0,0 +1,155 @@
+//! Fixed-size array of masked values with destructors, tracking which elements are live.
+
+use std::mem::size_of;
+use std::ptr::NonNull;
+
+use crate::MangledBoxArbitrary;
+
+/// Array of `N` masked slots for a type of any kind, e.g. one with a destructor, built on
+/// [`MangledBoxArbitrary`].
+///
+/// Unlike the box, the array knows which slots hold a value: a bitset records the live
+/// slots, so that [`Drop`] runs the destructor of exactly those. Each access unmangles
+/// only the slot concerned; the other slots stay masked.
+pub struct MangledArrayArbitrary<T, const N: usize> {
+    slots: MangledBoxArbitrary<[T; N]>,
+
+    /// Bit `i % 64` of word `i / 64` is set while slot `i` holds an initialized value.
+    live: Box<[u64]>,
+}
+
+impl<T, const N: usize> MangledArrayArbitrary<T, N> {
+    /// Creates an array with all slots empty.
+    pub fn new() -> Self {
+        Self { slots: MangledBoxArbitrary::new(), live: vec![0; N.div_ceil(64)].into() }
+    }
+
+    /// Returns whether slot `i` holds a value. This is non-secret metadata.
+    ///
+    /// # Panics
+    /// Panics if `i` is out of bounds.
+    pub fn is_live(&self, i: usize) -> bool {
+        assert!(i < N, "slot {i} is out of bounds of {N} slots");
+        self.live[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_live(&mut self, i: usize, live: bool) {
+        if live {
+            self.live[i / 64] |= 1 << (i % 64);
+        } else {
+            self.live[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    /// Unmangles slot `i` and invokes the provided closure on a pointer to it.
+    fn with_slot<F, R>(&mut self, i: usize, f: F) -> R
+    where
+        F: FnOnce(NonNull<T>) -> R,
+    {
+        self.slots.with_unmangled_range(i * size_of::<T>(), size_of::<T>(), |p| f(p.cast()))
+    }
+
+    /// Constructs a value in slot `i` in place; the pointer passed to the constructor points
+    /// to uninitialized memory suitable for `T`. A value already in the slot is dropped first.
+    /// The slot counts as live once the constructor returns.
+    ///
+    /// # Panics
+    /// Panics if `i` is out of bounds.
+    pub fn with_element(&mut self, i: usize, constructor: impl FnOnce(NonNull<T>)) {
+        self.drop_element(i);
+        self.with_slot(i, constructor);
+        self.set_live(i, true);
+    }
+
+    /// Unmangles the value in slot `i` and invokes the provided closure on it. Whether the
+    /// closure panics or returns normally, the slot is remangled.
+    ///
+    /// Returns [`None`] without calling the closure if the slot is empty.
+    ///
+    /// # Panics
+    /// Panics if `i` is out of bounds.
+    pub fn map_element<R>(&mut self, i: usize, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        if !self.is_live(i) {
+            return None;
+        }
+        // # Safety
+        // A live slot holds an initialized value, and the reference does not outlive
+        // the closure call.
+        Some(self.with_slot(i, |mut p| f(unsafe { p.as_mut() })))
+    }
+
+    /// Runs the destructor of the value in slot `i`, leaving the slot empty. Returns whether
+    /// there was a value.
+    ///
+    /// # Panics
+    /// Panics if `i` is out of bounds.
+    pub fn drop_element(&mut self, i: usize) -> bool {
+        if !self.is_live(i) {
+            return false;
+        }
+        // The slot is marked empty first, so that a panicking destructor cannot lead to
+        // a second drop.
+        self.set_live(i, false);
+        // # Safety
+        // The slot was live, so it holds an initialized value, dropped exactly once here.
+        self.with_slot(i, |p| unsafe { p.drop_in_place() });
+        true
+    }
+}
+
+impl<T, const N: usize> Default for MangledArrayArbitrary<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for MangledArrayArbitrary<T, N> {
+    fn drop(&mut self) {
+        for i in 0..N {
+            self.drop_element(i);
+        }
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn drops_live_elements_only() {
+        let tracker = Rc::new(());
+        let mut array = MangledArrayArbitrary::<Rc<()>, 70>::new();
+        for i in [0, 3, 69] {
+            array.with_element(i, |p| unsafe { p.write(Rc::clone(&tracker)) });
+        }
+        assert_eq!(Rc::strong_count(&tracker), 4);
+        assert!(array.is_live(69) && !array.is_live(68));
+
+        assert!(array.drop_element(3));
+        assert!(!array.drop_element(3));
+        assert_eq!(Rc::strong_count(&tracker), 3);
+
+        array.with_element(0, |p| unsafe { p.write(Rc::new(())) });
+        assert_eq!(Rc::strong_count(&tracker), 2, "replaced value not dropped");
+
+        drop(array);
+        assert_eq!(Rc::strong_count(&tracker), 1);
+    }
+
+    #[test]
+    fn elements_round_trip() {
+        let mut array = MangledArrayArbitrary::<String, 3>::new();
+        array.with_element(1, |p| unsafe { p.write(String::from("second")) });
+        assert_eq!(array.map_element(0, |s| s.len()), None);
+        array.map_element(1, |s| s.push('!'));
+        assert_eq!(array.map_element(1, |s| s.clone()).as_deref(), Some("second!"));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn out_of_bounds() {
+        MangledArrayArbitrary::<u8, 3>::new().is_live(3);
+    }
+}
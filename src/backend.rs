@@ -0,0 +1,64 @@
+//! Introspection of the XOR implementation compiled into this build.
+
+/// Implementation XORing the contents of a [`MangledBoxArbitrary`](crate::MangledBoxArbitrary),
+/// as reported by [`active_backend`].
+///
+/// [`MangledBox`](crate::MangledBox) never needs assembly, as its contents have no padding,
+/// and always uses portable volatile word stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// x86_64 assembly XORing a byte at a time, for targets without SSE2.
+    X86Scalar,
+    /// x86_64 assembly XORing 16 bytes at a time with SSE2, the x86_64 default.
+    X86Sse2,
+    /// x86_64 assembly XORing 32 bytes at a time with AVX2.
+    X86Avx2,
+    /// aarch64 assembly XORing a byte at a time.
+    Aarch64Scalar,
+    /// No assembly path for this architecture: only `MangledBox` is available.
+    PortableVolatile,
+    /// Running under Miri, which cannot execute the assembly path.
+    Miri,
+}
+
+/// Returns the XOR implementation compiled into this build. This is non-secret metadata,
+/// e.g. for logging at startup to confirm that production machines get the wide path.
+///
+/// The implementation is picked at compile time from the statically enabled target
+/// features, with no runtime CPU detection, so the result is the same for the whole run.
+pub const fn active_backend() -> Backend {
+    if cfg!(miri) {
+        Backend::Miri
+    } else if cfg!(all(target_arch = "x86_64", target_feature = "avx2")) {
+        Backend::X86Avx2
+    } else if cfg!(all(target_arch = "x86_64", target_feature = "sse2")) {
+        Backend::X86Sse2
+    } else if cfg!(target_arch = "x86_64") {
+        Backend::X86Scalar
+    } else if cfg!(target_arch = "aarch64") {
+        Backend::Aarch64Scalar
+    } else {
+        Backend::PortableVolatile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_build() {
+        let backend = active_backend();
+        if cfg!(miri) {
+            assert_eq!(backend, Backend::Miri);
+        } else if cfg!(target_arch = "x86_64") {
+            assert!(matches!(backend, Backend::X86Scalar | Backend::X86Sse2 | Backend::X86Avx2));
+            if cfg!(target_feature = "avx2") {
+                assert_eq!(backend, Backend::X86Avx2);
+            }
+        } else if cfg!(target_arch = "aarch64") {
+            assert_eq!(backend, Backend::Aarch64Scalar);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Masked monotonic counter, e.g. for deriving nonces.
+
+use crate::MangledBox;
+
+/// Secret `u64` counter kept masked at rest, unmasked only for the duration of an increment.
+///
+/// Increments panic instead of wrapping around, as a wrapped nonce counter would repeat
+/// nonces; the contents are remangled before the panic propagates.
+pub struct MangledCounter {
+    value: MangledBox<u64>,
+}
+
+impl MangledCounter {
+    /// Creates a counter starting at zero.
+    pub fn new() -> Self {
+        Self::starting_at(0)
+    }
+
+    /// Creates a counter starting at `value`.
+    pub fn starting_at(value: u64) -> Self {
+        let mut this = Self { value: MangledBox::new() };
+        this.value.with_unmangled(|p| unsafe { p.write(value) });
+        this
+    }
+
+    /// Increments the counter and returns the new value.
+    pub fn increment(&mut self) -> u64 {
+        self.value.with_unmangled(|p| {
+            // # Safety
+            // The box was initialized by the constructor and only ever holds `u64`s.
+            let next = unsafe { p.read() }.checked_add(1).expect("counter overflow");
+            unsafe { p.write(next) };
+            next
+        })
+    }
+
+    /// Increments the counter without handing the new value out.
+    ///
+    /// XOR masking does not commute with addition: with `m = v ^ k`, the masked form of
+    /// `v + 1` depends on how far the carry propagates through `v`, which is secret. So
+    /// the increment cannot be done on masked bytes alone and the value is unmasked
+    /// briefly, exactly as in [`MangledCounter::increment`]; this only saves the caller
+    /// from holding a plaintext copy afterwards.
+    pub fn increment_masked(&mut self) {
+        self.increment();
+    }
+}
+
+impl Default for MangledCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    #[test]
+    fn counts() {
+        let mut counter = MangledCounter::new();
+        assert_eq!(counter.increment(), 1);
+        counter.increment_masked();
+        assert_eq!(counter.increment(), 3);
+        assert_eq!(MangledCounter::starting_at(41).increment(), 42);
+    }
+
+    #[test]
+    fn overflow_panics_without_wrapping() {
+        let mut counter = MangledCounter::starting_at(u64::MAX);
+        let result = catch_unwind(AssertUnwindSafe(|| counter.increment()));
+        assert!(result.is_err());
+        counter.value.with_unmangled(|p| assert_eq!(unsafe { p.read() }, u64::MAX));
+    }
+}
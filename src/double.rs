@@ -0,0 +1,171 @@
+//! Two-level masking, with the data key itself kept masked by a second key.
+
+use std::mem::{MaybeUninit, size_of};
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, Ordering};
+
+use bytemuck::{NoUninit, Pod};
+
+use crate::{MangledBox, SecretContainer, keygen};
+use crate::nouninit::with_diff_scratch;
+
+/// Variant of [`MangledBox`] whose key is not stored in the clear either: the data is masked
+/// by `key1`, and `key1` is kept masked by `key2`, each in its own heap allocation. A memory
+/// snapshot then has to capture three allocations rather than two to recover the secret.
+///
+/// Every access first unmasks `key1`, then the data, and remasks them in reverse order,
+/// so it costs roughly three times as much as one on a [`MangledBox`].
+pub struct DoubleMangledBox<T: NoUninit> {
+    /// Data masked by `key1`; the key allocation of this box holds `key1 ^ key2`.
+    inner: MangledBox<T>,
+
+    /// Heap allocation containing `key2`, a cryptographically secure random key.
+    /// Each and every byte of the buffer is initialized.
+    key2: Box<MaybeUninit<T>>,
+}
+
+impl<T: NoUninit> DoubleMangledBox<T> {
+    /// Creates a new box with arbitrary contents.
+    pub fn new() -> Self {
+        let mut key2 = Box::<T>::new_uninit();
+        keygen::fill(key2.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key2`] is fully initialized
+
+        let mut inner = MangledBox::new();
+        // # Safety
+        // [`key2`] is fully initialized, see above.
+        unsafe { inner.xor_key(&key2) };
+        Self { inner, key2 }
+    }
+
+    /// Returns the size of the masked value in bytes, which is `size_of::<T>()`.
+    ///
+    /// This is non-secret metadata and does not touch the masked contents.
+    pub fn len_bytes(&self) -> usize {
+        size_of::<T>()
+    }
+
+    /// Returns whether the masked value is zero-sized, i.e. there is nothing to mask.
+    pub fn is_empty(&self) -> bool {
+        size_of::<T>() == 0
+    }
+
+    /// Unmangles the contents and invokes the provided closure on it.
+    /// Whether the closure panics or returns normally, the contents
+    /// and the first-level key are remangled.
+    pub fn with_unmangled<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(NonNull<T>) -> R,
+    {
+        // # Safety
+        // [`key2`] is fully initialized (type invariant); the inner closure only unmangles.
+        unsafe { self.inner.with_key_unmasked(&self.key2, |inner| inner.with_unmangled(f)) }
+    }
+
+    /// Rekeys both levels, preserving the contents.
+    ///
+    /// Neither the contents nor `key1` appear unmasked at any point.
+    pub fn rekey(&mut self) {
+        // XORing into both the data and the stored `key1 ^ key2` replaces `key1`.
+        self.inner.rekey();
+
+        with_diff_scratch(|diff_key: &mut MaybeUninit<T>| {
+            keygen::fill(diff_key.as_bytes_mut());
+            // ^ keygen::fill guarantees that [`diff_key`] is fully initialized
+
+            // # Safety
+            // [`diff_key`] is fully initialized, see above.
+            unsafe { self.inner.xor_key(diff_key) };
+            for (byte, diff) in self.key2.as_bytes_mut().iter_mut().zip(diff_key.as_bytes()) {
+                // # Safety
+                // All bytes of both buffers are initialized.
+                unsafe { byte.write(byte.assume_init() ^ diff.assume_init()) };
+            }
+        })
+    }
+}
+
+impl<T: NoUninit> Default for DoubleMangledBox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Pod> SecretContainer for DoubleMangledBox<T> {
+    type Value = T;
+
+    fn rekey(&mut self) {
+        DoubleMangledBox::rekey(self);
+    }
+
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        // # Safety
+        // All bytes of the contents are initialized (type invariant), and any initialized
+        // bit pattern is a valid `T: Pod`.
+        Some(self.with_unmangled(|mut p| f(unsafe { p.as_mut() })))
+    }
+}
+
+impl<T: NoUninit> Drop for DoubleMangledBox<T> {
+    fn drop(&mut self) {
+        // The inner box wipes the data and the masked `key1` by itself.
+        let key2 = Box::as_mut_ptr(&mut self.key2).cast::<u8>();
+
+        // # Safety
+        // [`key2`] points to an allocation of `size_of::<T>()` bytes.
+        #[cfg(feature = "sentinel-wipe")]
+        unsafe {
+            crate::sentinel::fill(key2, size_of::<T>());
+        }
+
+        #[cfg(not(feature = "sentinel-wipe"))]
+        for i in 0..size_of::<T>() {
+            // # Safety
+            // [`key2`] points to an allocation of `size_of::<T>()` bytes.
+            unsafe { key2.add(i).write_volatile(0) };
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_rekey() {
+        let mut box_ = DoubleMangledBox::<[u64; 3]>::new();
+        assert_eq!(box_.len_bytes(), 24);
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3]) });
+        box_.rekey();
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3]));
+        assert_eq!(box_.map_mut(|v| v[1]), Some(2));
+    }
+
+    #[test]
+    fn stored_pieces_reveal_nothing_in_pairs() {
+        let mut box_ = DoubleMangledBox::<[u8; 32]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([0x5a; 32]) });
+
+        let inner = std::mem::take(&mut box_.inner);
+        let (masked, stored_key) = inner.into_shares();
+        let key2 = unsafe { box_.key2.assume_init_read() };
+        let xor = |a: &[u8], b: &[u8]| a.iter().zip(b).map(|(x, y)| x ^ y).collect::<Vec<_>>();
+
+        assert_ne!(*masked, [0x5a; 32]);
+        assert_ne!(xor(&masked, &stored_key), [0x5a; 32]);
+        assert_ne!(xor(&masked, &key2), [0x5a; 32]);
+        assert_eq!(xor(&xor(&masked, &stored_key), &key2), [0x5a; 32]);
+    }
+
+    #[test]
+    fn remasks_on_panic() {
+        let mut box_ = DoubleMangledBox::<u64>::new();
+        box_.with_unmangled(|p| unsafe { p.write(42) });
+        let res = catch_unwind(AssertUnwindSafe(|| box_.with_unmangled(|_| panic!("closure failed"))));
+        assert!(res.is_err());
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, 42));
+    }
+}
@@ -0,0 +1,151 @@
+//! Allocator surrounding each block with inaccessible guard pages, so that out-of-bounds
+//! accesses next to masked data or keys fault instead of silently reading them.
+
+use std::alloc::{AllocError, Allocator, Global, Layout};
+use std::ptr::NonNull;
+use std::sync::atomic::{fence, Ordering};
+
+/// Allocator mapping every block in its own pages, with a `PROT_NONE` page on each side.
+///
+/// Use it through the `new_in` constructors, e.g. `MangledBox::<[u8; 32], _>::new_in(GuardPages::new())`;
+/// both the data and the key then get their own guarded mapping.
+///
+/// Each block ends exactly at the trailing guard page (up to its alignment), so even a one
+/// byte overread traps; an underread traps once it crosses the start of the block's first
+/// page. On deallocation the block is zeroed before the whole mapping is unmapped.
+///
+/// The cost is a memory mapping per allocation: the block's size rounded up to whole pages,
+/// plus two guard pages of address space. A box of a small `T` thus takes two pages of
+/// memory (data and key) and two system calls per allocation. Blocks smaller than the
+/// threshold set with [`GuardPages::with_min_size`] are served by the global allocator
+/// instead; [`GuardPages::new`] guards blocks of any size.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GuardPages {
+    min_size: usize,
+}
+
+impl GuardPages {
+    /// Creates an allocator guarding every block.
+    pub const fn new() -> Self {
+        Self { min_size: 0 }
+    }
+
+    /// Creates an allocator guarding blocks of at least `min_size` bytes only.
+    pub const fn with_min_size(min_size: usize) -> Self {
+        Self { min_size }
+    }
+
+    /// Whether a block with `layout` gets its own mapping rather than a global allocation.
+    fn guards(&self, layout: Layout) -> bool {
+        layout.size() != 0 && layout.size() >= self.min_size
+    }
+
+    fn page_size() -> usize {
+        // # Safety
+        // `sysconf` has no preconditions.
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    /// Size of the accessible part of the mapping backing a block with `layout`, and the
+    /// offset of the block in it.
+    fn body(layout: Layout, page: usize) -> Result<(usize, usize), AllocError> {
+        if layout.align() > page {
+            return Err(AllocError);
+        }
+        let body = layout.size().checked_next_multiple_of(page).ok_or(AllocError)?;
+        let offset = (body - layout.size()) & !(layout.align() - 1);
+        Ok((body, offset))
+    }
+}
+
+unsafe impl Allocator for GuardPages {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.guards(layout) {
+            return Global.allocate(layout);
+        }
+        let page = Self::page_size();
+        let (body, offset) = Self::body(layout, page)?;
+        let total = body.checked_add(2 * page).ok_or(AllocError)?;
+
+        // # Safety
+        // 1. An anonymous private mapping at an address of the kernel's choice touches no
+        //    existing memory
+        // 2. The `mprotect` range lies within the mapping, starting past the leading guard page
+        unsafe {
+            let base = libc::mmap(
+                std::ptr::null_mut(),
+                total,
+                libc::PROT_NONE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return Err(AllocError);
+            }
+            let base = base.cast::<u8>();
+            if libc::mprotect(base.add(page).cast(), body, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+                libc::munmap(base.cast(), total);
+                return Err(AllocError);
+            }
+            let block = NonNull::new_unchecked(base.add(page + offset));
+            Ok(NonNull::slice_from_raw_parts(block, layout.size()))
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if !self.guards(layout) {
+            // # Safety
+            // Blocks with this layout come from the global allocator, see [`Self::allocate`].
+            return unsafe { Global.deallocate(ptr, layout) };
+        }
+        let page = Self::page_size();
+
+        // # Safety
+        // `ptr` was returned by [`Self::allocate`] with the same `layout`, so it lies
+        // `page + offset` bytes into a mapping of `body + 2 * page` bytes, and the block
+        // is readable and writable.
+        unsafe {
+            let (body, offset) = Self::body(layout, page).unwrap_unchecked();
+            for i in 0..layout.size() {
+                ptr.add(i).write_volatile(0);
+            }
+            fence(Ordering::SeqCst);
+            libc::munmap(ptr.sub(page + offset).as_ptr().cast(), body + 2 * page);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MangledBox;
+
+    #[derive(bytemuck::NoUninit, Clone, Copy, PartialEq, Debug)]
+    #[repr(C, align(16))]
+    struct Align16([u8; 48]);
+
+    #[test]
+    fn block_ends_at_guard_page() {
+        let page = GuardPages::page_size();
+        let mut box_ = MangledBox::<Align16, _>::new_in(GuardPages::new());
+        box_.with_unmangled(|p| {
+            assert_eq!(p.as_ptr().align_offset(16), 0, "alignment not preserved");
+            assert_eq!((p.as_ptr().addr() + 48) % page, 0, "block does not end at a page");
+            unsafe { p.write(Align16([7; 48])) };
+        });
+        box_.rekey();
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, Align16([7; 48])));
+    }
+
+    #[test]
+    fn small_blocks_below_threshold() {
+        let page = GuardPages::page_size();
+        let mut box_ = MangledBox::<[u8; 5], _>::new_in(GuardPages::with_min_size(64));
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3, 4, 5]) });
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3, 4, 5]));
+
+        let mut box_ = MangledBox::<[u8; 5], _>::new_in(GuardPages::new());
+        box_.with_unmangled(|p| assert_eq!((p.as_ptr().addr() + 5) % page, 0));
+    }
+}
@@ -0,0 +1,356 @@
+//! Generation of keys and rekeying differentials for the mangled containers.
+
+use std::mem::MaybeUninit;
+
+/// Largest second draw of the `paranoid-keygen` feature made in a single call.
+#[cfg(feature = "paranoid-keygen")]
+const SECOND_DRAW_CHUNK: usize = 1 << 20;
+
+/// Fills the buffer with cryptographically secure random bytes, returning it
+/// as initialized.
+///
+/// With the `paranoid-keygen` feature, the result is the XOR of two independent
+/// draws from the system entropy source, so that a single flawed call (e.g. one
+/// that leaves part of a large buffer in a weak state) cannot make the key
+/// predictable on its own.
+///
+/// With the `test-determinism` feature, a thread seeded by [`set_test_seed`] gets
+/// the bytes from a seeded PRNG instead.
+///
+/// An empty buffer, e.g. the key of a zero-sized type, is returned without drawing.
+///
+/// # Panics
+/// Panics if the system entropy source fails.
+pub(crate) fn fill(buf: &mut [MaybeUninit<u8>]) -> &mut [u8] {
+    if buf.is_empty() {
+        return &mut [];
+    }
+
+    #[cfg(test)]
+    {
+        DRAWS.set(DRAWS.get() + 1);
+        DRAWN_BYTES.set(DRAWN_BYTES.get() + buf.len());
+    }
+
+    #[cfg(feature = "test-determinism")]
+    if determinism::fill(buf) {
+        // # Safety
+        // determinism::fill initializes every byte when it returns true.
+        return unsafe { buf.assume_init_mut() };
+    }
+
+    let buf = getrandom::fill_uninit(buf).expect("no keygen");
+    // ^ fill_uninit guarantees that [`buf`] is fully initialized on success
+
+    #[cfg(feature = "paranoid-keygen")]
+    {
+        let mut second_draw = vec![0u8; buf.len().min(SECOND_DRAW_CHUNK)];
+        for chunk in buf.chunks_mut(SECOND_DRAW_CHUNK) {
+            let second_draw = &mut second_draw[..chunk.len()];
+            getrandom::fill(second_draw).expect("no keygen");
+            for (byte, extra) in chunk.iter_mut().zip(second_draw.iter()) {
+                *byte ^= *extra;
+            }
+        }
+
+        // The second draw is half of the key, so it must not linger on the heap.
+        for byte in second_draw.iter_mut() {
+            // # Safety
+            // The pointer comes from a mutable reference.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::SeqCst);
+    }
+
+    buf
+}
+
+/// Records the freshly generated key of a live box, identified by the address of its key
+/// allocation, replacing the box's previous key; with the `key-audit` feature in debug
+/// builds, asserts that no other live box holds the same key. A no-op otherwise.
+///
+/// # Safety
+/// Every byte of `key` must be initialized.
+#[cfg_attr(not(all(feature = "key-audit", debug_assertions)), allow(unused_variables))]
+pub(crate) unsafe fn audit_key<T>(key: &MaybeUninit<T>) {
+    // # Safety
+    // All bytes of [`key`] are initialized, see the contract above.
+    #[cfg(all(feature = "key-audit", debug_assertions))]
+    audit::register(std::ptr::from_ref(key).addr(), unsafe { key.as_bytes().assume_init_ref() });
+}
+
+/// Forgets the key of a box being dropped, see [`audit_key`].
+#[cfg_attr(not(all(feature = "key-audit", debug_assertions)), allow(unused_variables))]
+pub(crate) fn audit_forget<T>(key: &MaybeUninit<T>) {
+    #[cfg(all(feature = "key-audit", debug_assertions))]
+    audit::forget(std::ptr::from_ref(key).addr());
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of non-empty draws made by [`fill`] on the current thread.
+    static DRAWS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+
+    /// Total length of the buffers filled by [`fill`] on the current thread.
+    static DRAWN_BYTES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Returns the number of non-empty draws made by [`fill`] on the current thread.
+#[cfg(test)]
+pub(crate) fn draws() -> usize {
+    DRAWS.get()
+}
+
+/// Returns the total length of the buffers filled by [`fill`] on the current thread.
+#[cfg(test)]
+pub(crate) fn drawn_bytes() -> usize {
+    DRAWN_BYTES.get()
+}
+
+/// Smoke test of the entropy source as seen through the boxes: constructs `n` boxes
+/// and checks that their keys look pairwise independent, returning `false` if any two
+/// keys are identical or obviously correlated.
+///
+/// This is no test of cryptographic randomness. It compares 256-bit keys by Hamming
+/// distance, which for independent keys is 128 on average with a standard deviation of 8,
+/// and flags distances outside `64..=192`; independent keys land there except with
+/// negligible probability, while constant or repeating sources fail at once. Meant for
+/// CI and startup checks against misconfigured entropy sources.
+#[cfg(feature = "key-audit")]
+pub fn keys_look_independent(n: usize) -> bool {
+    let keys: Vec<[u8; 32]> = (0..n)
+        .map(|_| {
+            let (_, key) = crate::MangledBox::<[u8; 32]>::new().into_shares();
+            (*key).try_into().unwrap()
+        })
+        .collect();
+    pairwise_independent(&keys)
+}
+
+/// Checks that every two keys differ in `64..=192` of their 256 bits.
+#[cfg(any(test, feature = "key-audit"))]
+fn pairwise_independent(keys: &[[u8; 32]]) -> bool {
+    keys.iter().enumerate().all(|(i, a)| {
+        keys[i + 1..].iter().all(|b| {
+            let distance: u32 = a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum();
+            (64..=192).contains(&distance)
+        })
+    })
+}
+
+#[cfg(feature = "test-determinism")]
+pub use determinism::{clear_test_seed, set_test_seed};
+
+/// Seeded key generation for reproducible tests.
+#[cfg(feature = "test-determinism")]
+mod determinism {
+    use std::cell::Cell;
+    use std::mem::MaybeUninit;
+
+    thread_local! {
+        /// State of the SplitMix64 generator, if the thread is seeded.
+        static STATE: Cell<Option<u64>> = const { Cell::new(None) };
+    }
+
+    /// Makes all subsequent keys drawn on the current thread, by construction and rekeying
+    /// alike, come from a PRNG seeded with `seed`, so that masked representations are
+    /// reproducible in tests.
+    ///
+    /// **Keys drawn this way are predictable and provide no protection at all.** This is
+    /// only available with the `test-determinism` feature, which must never be enabled
+    /// outside of tests, e.g. by listing it in `[dev-dependencies]` only.
+    pub fn set_test_seed(seed: u64) {
+        STATE.set(Some(seed));
+    }
+
+    /// Makes the current thread draw keys from the system entropy source again.
+    pub fn clear_test_seed() {
+        STATE.set(None);
+    }
+
+    /// Fills the buffer from the seeded PRNG, or returns `false` without touching it if the
+    /// thread is not seeded.
+    pub(super) fn fill(buf: &mut [MaybeUninit<u8>]) -> bool {
+        let Some(mut state) = STATE.get() else {
+            return false;
+        };
+        for chunk in buf.chunks_mut(8) {
+            state = state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^= z >> 31;
+            for (byte, random) in chunk.iter_mut().zip(z.to_le_bytes()) {
+                byte.write(random);
+            }
+        }
+        STATE.set(Some(state));
+        true
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn draw() -> [u8; 20] {
+            let mut buf = [MaybeUninit::uninit(); 20];
+            super::super::fill(&mut buf).try_into().unwrap()
+        }
+
+        #[test]
+        fn seeded_draws_repeat() {
+            set_test_seed(7);
+            let first = (draw(), draw());
+            set_test_seed(7);
+            assert_eq!((draw(), draw()), first);
+            assert_ne!(first.0, first.1);
+
+            set_test_seed(8);
+            assert_ne!(draw(), first.0);
+            clear_test_seed();
+            assert_ne!(draw(), first.0);
+        }
+    }
+}
+
+/// Diagnostic registry of the keys of live boxes, asserting that no two of them hold the
+/// same key; catches broken entropy sources (e.g. one returning constant bytes) in debug
+/// builds. Other draws, such as rekeying differentials, are not recorded.
+#[cfg(all(feature = "key-audit", debug_assertions))]
+mod audit {
+    use std::collections::HashMap;
+    use std::hash::{BuildHasher, RandomState};
+    use std::sync::{LazyLock, Mutex, PoisonError};
+
+    /// Keys shorter than this repeat by chance too often to be audited.
+    const MIN_LEN: usize = 8;
+
+    struct Registry {
+        /// Randomly keyed, so that the stored hashes reveal nothing about the keys.
+        state: RandomState,
+        /// Hash of the current key of each live box, by the address of its key allocation.
+        by_owner: HashMap<usize, u64>,
+        /// Box holding each recorded key.
+        by_hash: HashMap<u64, usize>,
+    }
+
+    static REGISTRY: LazyLock<Mutex<Registry>> = LazyLock::new(|| {
+        Mutex::new(Registry {
+            state: RandomState::new(),
+            by_owner: HashMap::new(),
+            by_hash: HashMap::new(),
+        })
+    });
+
+    /// Records `key` as the current key of the box `owner`, asserting that no other live
+    /// box holds it.
+    pub(super) fn register(owner: usize, key: &[u8]) {
+        if key.len() < MIN_LEN {
+            return;
+        }
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        let hash = registry.state.hash_one(key);
+        if let Some(previous) = registry.by_owner.insert(owner, hash) {
+            registry.by_hash.remove(&previous);
+        }
+        let holder = *registry.by_hash.entry(hash).or_insert(owner);
+        drop(registry);
+        debug_assert!(holder == owner, "key-audit: two live boxes hold the same {}-byte key", key.len());
+    }
+
+    /// Drops the record of the box `owner`, if any.
+    pub(super) fn forget(owner: usize) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(hash) = registry.by_owner.remove(&owner)
+            && registry.by_hash.get(&hash) == Some(&owner)
+        {
+            registry.by_hash.remove(&hash);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn distinct_short_and_replaced_keys_pass() {
+            register(1, b"key-audit test: distinct 1");
+            register(2, b"key-audit test: distinct 2");
+            register(3, b"short");
+            register(4, b"short");
+
+            register(1, b"key-audit test: rekeyed 1");
+            register(5, b"key-audit test: distinct 1");
+            forget(2);
+            register(6, b"key-audit test: distinct 2");
+            [1, 3, 4, 5, 6].into_iter().for_each(forget);
+        }
+
+        #[test]
+        #[should_panic(expected = "two live boxes")]
+        fn repeated_live_key_asserts() {
+            register(7, b"key-audit test: repeated");
+            register(8, b"key-audit test: repeated");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drawn_keys_independent() {
+        let keys: Vec<[u8; 32]> = (0..32)
+            .map(|_| fill(&mut [MaybeUninit::uninit(); 32]).try_into().unwrap())
+            .collect();
+        assert!(pairwise_independent(&keys));
+
+        assert!(!pairwise_independent(&[[0x5a; 32], [0x5a; 32]]), "identical keys");
+        assert!(!pairwise_independent(&[[0x00; 32], [0x01; 32]]), "correlated keys");
+        assert!(!pairwise_independent(&[[0x00; 32], [0xff; 32]]), "complementary keys");
+    }
+
+    #[test]
+    #[cfg(feature = "key-audit")]
+    fn boxes_keys_independent() {
+        assert!(keys_look_independent(16));
+    }
+
+    #[test]
+    #[cfg(all(feature = "key-audit", feature = "test-determinism", debug_assertions))]
+    fn audit_tracks_live_box_keys() {
+        let seeded_box = || {
+            set_test_seed(0x5eed);
+            let box_ = crate::MangledBox::<[u8; 32]>::new();
+            clear_test_seed();
+            box_
+        };
+
+        // A dropped box's key may reappear, as may rekeying differentials.
+        drop(seeded_box());
+        let first = seeded_box();
+        let (mut a, mut b) = (crate::MangledBox::<[u8; 32]>::new(), crate::MangledBox::<[u8; 32]>::new());
+        set_test_seed(1);
+        a.rekey();
+        set_test_seed(1);
+        b.rekey();
+        clear_test_seed();
+
+        let repeat = std::panic::catch_unwind(seeded_box);
+        clear_test_seed();
+        assert!(repeat.is_err(), "two live boxes with the same key not caught");
+        drop(first);
+    }
+
+    #[test]
+    fn fills_whole_buffer() {
+        // 4096 bytes of random data being all zero has negligible probability,
+        // so this only fails if part of the buffer is left unfilled.
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        let filled = fill(&mut buf);
+        for chunk in filled.chunks(64) {
+            assert!(chunk.iter().any(|&b| b != 0), "chunk left unfilled");
+        }
+    }
+}
@@ -1,13 +1,160 @@
-#![feature(maybe_uninit_as_bytes, box_vec_non_null, new_zeroed_alloc, box_as_ptr)]
-#![feature(clone_to_uninit)]
+#![feature(maybe_uninit_as_bytes, box_as_ptr, allocator_api)]
+#![cfg_attr(any(test, feature = "clone-to-uninit"), feature(clone_to_uninit))]
+#![feature(freeze)]
 
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub use arbitrary::MangledBoxArbitrary;
 #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
 pub mod arbitrary;
 
-pub use nouninit::MangledBox;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use array::MangledArrayArbitrary;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod array;
+
+pub use backend::{Backend, active_backend};
+pub mod backend;
+
+pub use nouninit::{ByValue, MangledBox, MaskedSnapshot, rekey_all, with_two};
+#[cfg(feature = "poison-on-panic")]
+pub use nouninit::Poisoned;
 pub mod nouninit;
 
+pub use scheme::{AddMod256, MaskScheme, Xor};
+pub mod scheme;
+
+pub use double::DoubleMangledBox;
+pub mod double;
+
+pub use maskable::Maskable;
+pub mod maskable;
+
+pub use masker::{MaskedRegion, Masker};
+pub mod masker;
+
 pub use option::MangledOption;
 pub mod option;
+
+pub use counter::MangledCounter;
+pub mod counter;
+
+pub use pool::{MangledPool, PooledMangledBox};
+pub mod pool;
+
+pub use rekey::{AutoRekey, RekeyPolicy};
+pub mod rekey;
+
+pub use ring::MangledRingBuffer;
+pub mod ring;
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub use selftest::{SelfTestError, self_test};
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub mod selftest;
+
+#[cfg(feature = "addr-randomize")]
+pub use addr_randomize::RandomizedAddr;
+#[cfg(feature = "addr-randomize")]
+pub mod addr_randomize;
+
+#[cfg(feature = "cacheline-isolate")]
+pub use cacheline_isolate::CachelineIsolated;
+#[cfg(feature = "cacheline-isolate")]
+pub mod cacheline_isolate;
+
+#[cfg(all(feature = "guard-pages", unix))]
+pub use guard_pages::GuardPages;
+#[cfg(all(feature = "guard-pages", unix))]
+pub mod guard_pages;
+
+#[cfg(feature = "sentinel-wipe")]
+pub use sentinel::{set_wipe_sentinel, wipe_sentinel};
+#[cfg(feature = "sentinel-wipe")]
+mod sentinel;
+
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+
+pub mod timing;
+
+#[cfg(feature = "test-determinism")]
+pub use keygen::{clear_test_seed, set_test_seed};
+
+#[cfg(feature = "key-audit")]
+pub use keygen::keys_look_independent;
+
+mod keygen;
+
+#[doc(hidden)]
+pub use macros::__copy_field;
+mod macros;
+
+
+/// A container holding a masked secret, implemented by [`MangledBox`], [`MangledBoxArbitrary`],
+/// [`DoubleMangledBox`] and [`MangledOption`] so that generic code (e.g. a "rekey everything"
+/// pass) does not need to match on the concrete type.
+///
+/// The boxes only implement this for [`bytemuck::Pod`] contents: they cannot know whether
+/// a value was ever written, so handing out `&mut Self::Value` is only sound when every bit
+/// pattern is a valid value.
+pub trait SecretContainer {
+    /// Type of the masked value.
+    type Value;
+
+    /// Rekeys the container, preserving its contents.
+    fn rekey(&mut self);
+
+    /// Unmangles the contents and invokes the provided closure on them, remangling afterwards.
+    /// Returns [`None`] without calling the closure if the container holds no value.
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut Self::Value) -> R) -> Option<R>;
+}
+
+
+/// Aborts the process when dropped, that is, when the code it covers unwinds
+/// instead of calling [`AbortOnUnwind::defuse`].
+///
+/// Remangling runs in guards' `Drop` implementations, often while unwinding
+/// already; a panic there would end in a confusing double-panic abort or leave
+/// the contents unmasked, so we abort right away with a clear message instead.
+pub(crate) struct AbortOnUnwind(pub(crate) &'static str);
+
+impl AbortOnUnwind {
+    /// Disarms the guard once the covered code has completed.
+    pub(crate) fn defuse(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        eprintln!("secretmangle: {}; aborting", self.0);
+        std::process::abort();
+    }
+}
+
+
+#[cfg(all(test, not(miri), any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod tests {
+    use super::*;
+
+    fn rekey_and_read<C: SecretContainer<Value = u64>>(container: &mut C) -> Option<u64> {
+        container.rekey();
+        container.map_mut(|v| *v)
+    }
+
+    #[test]
+    fn secret_container_is_uniform() {
+        let mut nouninit = MangledBox::<u64>::new();
+        nouninit.with_unmangled(|p| unsafe { p.write(1) });
+        assert_eq!(rekey_and_read(&mut nouninit), Some(1));
+
+        let mut arbitrary = MangledBoxArbitrary::<u64>::new();
+        arbitrary.with_unmangled(|p| unsafe { p.write(2) });
+        assert_eq!(rekey_and_read(&mut arbitrary), Some(2));
+
+        let mut option = MangledOption::filled_with_unmasked_value(3u64);
+        assert_eq!(rekey_and_read(&mut option), Some(3));
+        option.clear();
+        assert_eq!(rekey_and_read(&mut option), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Declarative front-end over [`SecretContainer::map_mut`](crate::SecretContainer::map_mut).
+
+/// Unmangles a [`SecretContainer`](crate::SecretContainer) for the duration of a block,
+/// encoding the safe usage pattern without raw pointers at the call site.
+///
+/// The container is given as a place expression (e.g. `box_` or `self.key`), which is
+/// borrowed mutably. Either form returns [`None`] without running the block if the
+/// container holds no value, as an empty [`MangledOption`](crate::MangledOption) does.
+///
+/// With a closure-like form, the block gets `&mut` access to the value, which cannot
+/// escape it:
+///
+/// ```
+/// use secretmangle::{MangledBox, with_secret};
+///
+/// let mut counter = MangledBox::<u64>::new_random();
+/// with_secret!(counter, |value| *value = 41);
+/// assert_eq!(with_secret!(counter, |value| { *value += 1; *value }), Some(42));
+/// ```
+///
+/// ```compile_fail
+/// use secretmangle::{MangledBox, with_secret};
+///
+/// let mut counter = MangledBox::<u64>::new_random();
+/// let leaked: Option<&mut u64> = with_secret!(counter, |value| value);
+/// ```
+///
+/// With a list of field names, the block gets copies of those fields instead, and no
+/// access to the value itself. Every field must be [`Copy`], which is checked at compile
+/// time. The copies are plaintext on the stack and are not wiped, so keep the block short.
+///
+/// ```
+/// use secretmangle::{MangledBox, with_secret};
+///
+/// #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+/// #[repr(C)]
+/// struct Credentials {
+///     user_id: u64,
+///     pin: u32,
+///     attempts: u32,
+/// }
+///
+/// let mut creds = MangledBox::<Credentials>::new();
+/// with_secret!(creds, |c| *c = Credentials { user_id: 7, pin: 1234, attempts: 0 });
+/// let ok = with_secret!(creds, { pin, attempts } => pin == 1234 && attempts < 3);
+/// assert_eq!(ok, Some(true));
+/// ```
+///
+/// ```compile_fail
+/// use secretmangle::{MangledOption, with_secret};
+///
+/// struct Login {
+///     password: String,
+/// }
+///
+/// let mut login = MangledOption::filled_with_unmasked_value(Login { password: "hunter2".into() });
+/// let len = with_secret!(login, { password } => password.len());
+/// ```
+#[macro_export]
+macro_rules! with_secret {
+    ($container:expr, |$secret:pat_param| $body:expr) => {
+        $crate::SecretContainer::map_mut(&mut $container, |$secret| $body)
+    };
+    ($container:expr, { $($field:ident),+ $(,)? } => $body:expr) => {
+        $crate::SecretContainer::map_mut(&mut $container, |secret| {
+            $(let $field = $crate::__copy_field(&secret.$field);)+
+            $body
+        })
+    };
+}
+
+/// Copies a field out in [`with_secret!`], requiring it to be [`Copy`].
+#[doc(hidden)]
+pub fn __copy_field<T: Copy>(field: &T) -> T {
+    *field
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MangledOption;
+
+    #[test]
+    fn empty_option_skips_block() {
+        let mut empty = MangledOption::<(u8, u8)>::new();
+        assert_eq!(with_secret!(empty, |_| unreachable!("block run on None")), None::<()>);
+
+        let mut pair = MangledOption::filled_with_unmasked_value((1u8, 2u8));
+        assert_eq!(with_secret!(pair, |p| p.0 + p.1), Some(3));
+    }
+}
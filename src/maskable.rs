@@ -0,0 +1,79 @@
+//! Opt-in marker for types that are meaningful to mask.
+
+use std::num::Wrapping;
+
+use bytemuck::NoUninit;
+
+/// Marker for [`NoUninit`] types holding plain data only, with no pointers or references.
+///
+/// Masking a pointer-bearing type is byte-safe, but the masked pointer is garbage, and
+/// dereferencing anything derived from it while it is masked is undefined behaviour.
+/// `bytemuck` keeps raw pointers out of [`NoUninit`] unless its `unsound_ptr_pod_impl`
+/// feature is on, but a derived type may still smuggle an address in, e.g. as a `usize`.
+/// [`MangledBox::new_plain_data`](crate::MangledBox::new_plain_data) only accepts types
+/// implementing this trait, so every user type has to opt in explicitly:
+///
+/// ```compile_fail
+/// use bytemuck::NoUninit;
+/// use secretmangle::MangledBox;
+///
+/// #[derive(Clone, Copy, NoUninit)]
+/// #[repr(C)]
+/// struct Handle {
+///     addr: usize,
+/// }
+///
+/// let secret = MangledBox::<Handle>::new_plain_data();
+/// ```
+///
+/// The trait is implemented for integers, floats, `bool`, `char`, `()`, [`Wrapping`] and
+/// arrays of those. For your own plain-data types, implement it by hand:
+///
+/// ```
+/// use bytemuck::NoUninit;
+/// use secretmangle::{Maskable, MangledBox};
+///
+/// #[derive(Clone, Copy, NoUninit)]
+/// #[repr(C)]
+/// struct Credentials {
+///     user_id: u64,
+///     token: [u8; 24],
+/// }
+///
+/// // # Safety
+/// // Both fields are `Maskable`, so `Credentials` holds no pointers either.
+/// unsafe impl Maskable for Credentials {}
+///
+/// let secret = MangledBox::<Credentials>::new_plain_data();
+/// ```
+///
+/// The pointer-sized integers `usize` and `isize` implement the trait too, as they mostly
+/// hold lengths and indices. The trait cannot tell those from addresses cast to integers:
+/// masking a `usize` that holds an address is allowed, and not dereferencing anything
+/// derived from it while masked is the caller's responsibility.
+///
+/// # Safety
+/// The type must not contain raw pointers, references, or anything built on them
+/// (e.g. [`Box`], `fn` pointers), directly or through fields, nor fields dedicated to
+/// addresses stored as integers.
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not known to be plain data safe to mask",
+    note = "if it holds no pointers or references, add `unsafe impl Maskable for {Self} {{}}`"
+)]
+pub unsafe trait Maskable: NoUninit {}
+
+macro_rules! impl_maskable {
+    ($($t:ty),* $(,)?) => {
+        $(unsafe impl Maskable for $t {})*
+    };
+}
+
+impl_maskable!(
+    u8, u16, u32, u64, u128, usize,
+    i8, i16, i32, i64, i128, isize,
+    f32, f64, bool, char, (),
+);
+
+unsafe impl<T: Maskable, const N: usize> Maskable for [T; N] where [T; N]: NoUninit {}
+
+unsafe impl<T: Maskable> Maskable for Wrapping<T> where Wrapping<T>: NoUninit {}
@@ -0,0 +1,134 @@
+//! In-place masking of borrowed buffers.
+
+use std::sync::atomic::{fence, Ordering};
+
+use crate::keygen;
+
+/// XORs `key` into `buf` with volatile stores, followed by a fence as in the boxes.
+fn xor_volatile(buf: &mut [u8], key: &[u8]) {
+    debug_assert_eq!(buf.len(), key.len());
+    for (byte, key) in buf.iter_mut().zip(key) {
+        // # Safety
+        // The pointer comes from a live `&mut u8`.
+        unsafe { std::ptr::write_volatile(byte, *byte ^ key) };
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// Entry point for masking secrets in buffers the caller does not own, e.g. a decrypted
+/// region of a memory mapping, for as long as they sit idle.
+pub struct Masker;
+
+impl Masker {
+    /// Masks `buf` in place with a fresh random key, returning the guard that holds the key.
+    ///
+    /// The buffer stays masked while the guard lives and is unmasked back to its original
+    /// contents when the guard is dropped.
+    pub fn mask_in_place(buf: &mut [u8]) -> MaskedRegion<'_> {
+        let mut key = Box::new_uninit_slice(buf.len());
+        keygen::fill(&mut key);
+        // # Safety
+        // keygen::fill guarantees that [`key`] is fully initialized
+        let key = unsafe { key.assume_init() };
+
+        xor_volatile(buf, &key);
+        MaskedRegion { buf, key }
+    }
+}
+
+/// Borrowed buffer masked in place by [`Masker::mask_in_place`].
+///
+/// Dropping the region restores the plaintext into the buffer, which is then again
+/// the caller's to protect or zero. Leaking the region (e.g. with [`std::mem::forget`])
+/// leaves the buffer masked, and the contents lost.
+pub struct MaskedRegion<'a> {
+    buf: &'a mut [u8],
+
+    /// Heap allocation of `buf.len()` random bytes.
+    key: Box<[u8]>,
+}
+
+/// Remasks the region when dropped, whether the closure panicked or returned.
+struct RemaskGuard<'r, 'a>(&'r mut MaskedRegion<'a>);
+
+impl Drop for RemaskGuard<'_, '_> {
+    fn drop(&mut self) {
+        let region = &mut *self.0;
+        xor_volatile(region.buf, &region.key);
+    }
+}
+
+impl MaskedRegion<'_> {
+    /// Returns the length of the region in bytes. This is non-secret metadata.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns whether the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Unmasks the buffer and invokes the provided closure on it.
+    /// Whether the closure panics or returns normally, the buffer is remasked,
+    /// including any changes the closure made to it.
+    pub fn with_unmasked<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        xor_volatile(self.buf, &self.key);
+        let guard = RemaskGuard(self);
+        f(guard.0.buf)
+    }
+}
+
+impl Drop for MaskedRegion<'_> {
+    fn drop(&mut self) {
+        xor_volatile(self.buf, &self.key);
+        for byte in self.key.iter_mut() {
+            // # Safety
+            // The pointer comes from a live `&mut u8`.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    #[test]
+    fn masks_while_alive_and_restores_on_drop() {
+        let mut buf = *b"borrowed secret bytes in a mmap";
+        let mut region = Masker::mask_in_place(&mut buf);
+        assert_eq!(region.len(), 31);
+        region.with_unmasked(|plain| {
+            assert_eq!(plain, b"borrowed secret bytes in a mmap");
+            plain[0] = b'B';
+        });
+        assert_ne!(&region.buf[..], b"Borrowed secret bytes in a mmap");
+        drop(region);
+        assert_eq!(&buf, b"Borrowed secret bytes in a mmap");
+    }
+
+    #[test]
+    fn remasks_on_panic() {
+        let mut buf = [7u8; 16];
+        let mut region = Masker::mask_in_place(&mut buf);
+        let res = catch_unwind(AssertUnwindSafe(|| region.with_unmasked(|_| panic!("closure failed"))));
+        assert!(res.is_err());
+        assert_ne!(&region.buf[..], [7; 16]);
+        drop(region);
+        assert_eq!(buf, [7; 16]);
+    }
+
+    #[test]
+    fn empty() {
+        let mut region = Masker::mask_in_place(&mut []);
+        assert!(region.is_empty());
+        region.with_unmasked(|plain| assert!(plain.is_empty()));
+    }
+}
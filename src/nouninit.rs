@@ -1,9 +1,22 @@
-use std::sync::atomic::{fence, Ordering};
-use std::mem::{MaybeUninit, size_of};
+use std::sync::atomic::{AtomicUsize, fence, Ordering};
+#[cfg(feature = "poison-on-panic")]
+use std::sync::atomic::AtomicBool;
+use std::sync::OnceLock;
+use std::cell::UnsafeCell;
+use std::mem::{MaybeUninit, align_of, size_of};
+use std::ops::BitXor;
 use std::marker::PhantomData;
 use std::ptr::NonNull;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::alloc::{Allocator, Global};
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
 
-use bytemuck::NoUninit;
+use bytemuck::{NoUninit, Pod};
+
+use crate::{AbortOnUnwind, Maskable, SecretContainer, keygen};
+use crate::scheme::{MaskScheme, Xor};
+use crate::timing::OverrunCheck;
 
 /// XORs the data behind first pointer using key from second pointer.
 /// The mangling operation is guaranteed to not be reordered after
@@ -18,15 +31,131 @@ use bytemuck::NoUninit;
 /// - `key` must point to at least `size_of::<T>()` initialized bytes
 ///   valid for `u8` reads
 /// - `data` and `key` must either be non-overlapping or the same
-unsafe fn xor_chunks<T>(data: *mut u8, key: *const u8) {
-    for i in 0..size_of::<T>() {
-        let data_byte = unsafe { *data.wrapping_add(i) };
-        let key_byte = unsafe { *key.wrapping_add(i) };
+pub(crate) unsafe fn xor_chunks<T>(data: *mut u8, key: *const u8) {
+    if size_of::<T>() == 0 {
+        // No bytes to mask, so no access to order either.
+        return;
+    }
+    unsafe { xor_chunks_nofence::<T>(data, key) }
+    fence(Ordering::SeqCst);
+}
+
+/// Largest rekeying differential built on the stack.
+const STACK_DIFF_MAX: usize = 4096;
+
+/// Invokes `f` on a zeroed scratch buffer for a `T`-sized rekeying differential: on the
+/// stack, or in a heap buffer if larger than [`STACK_DIFF_MAX`], so that rekeying
+/// megabyte-sized secrets cannot overflow the stack. The buffer is wiped once `f` returns,
+/// as the differential links the old key to the new one.
+pub(crate) fn with_diff_scratch<T, R>(f: impl FnOnce(&mut MaybeUninit<T>) -> R) -> R {
+    if size_of::<T>() > STACK_DIFF_MAX {
+        let mut scratch = Box::<T>::new_zeroed();
+        let result = f(&mut scratch);
+        wipe_bytes(scratch.as_bytes_mut());
+        result
+    } else {
+        diff_scratch_on_stack(f)
+    }
+}
+
+/// Stack path of [`with_diff_scratch`], kept out of line: a buffer declared in the caller
+/// would take up its frame even when the heap path is taken.
+#[inline(never)]
+fn diff_scratch_on_stack<T, R>(f: impl FnOnce(&mut MaybeUninit<T>) -> R) -> R {
+    let mut scratch = MaybeUninit::zeroed();
+    let result = f(&mut scratch);
+    wipe_bytes(scratch.as_bytes_mut());
+    result
+}
+
+/// Zeroes the bytes with volatile stores followed by a fence, so that the wipe of a
+/// scratch buffer is neither elided nor reordered after its deallocation.
+pub(crate) fn wipe_bytes(bytes: &mut [MaybeUninit<u8>]) {
+    for byte in bytes {
+        // # Safety
+        // The pointer comes from a live `&mut MaybeUninit<u8>`.
+        unsafe { std::ptr::write_volatile(byte, MaybeUninit::new(0)) };
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// XORs the data behind first pointer using key from second pointer, like [`xor_chunks`]
+/// but without the fence: callers batching several calls must emit one themselves.
+///
+/// # Safety
+/// Same as [`xor_chunks`].
+unsafe fn xor_chunks_nofence<T>(data: *mut u8, key: *const u8) {
+    if size_of::<T>() <= 16 {
+        // Small types are XORed in the widest words their alignment permits;
+        // the trip count is a constant, so the loop gets fully unrolled.
         unsafe {
-            data.wrapping_add(i).write_volatile(data_byte ^ key_byte);
+            match align_of::<T>() {
+                1 => xor_words::<T, u8>(data, key),
+                2 => xor_words::<T, u16>(data, key),
+                4 => xor_words::<T, u32>(data, key),
+                _ => xor_words::<T, u64>(data, key),
+            }
         }
+    } else {
+        unsafe { xor_words::<T, u8>(data, key) }
+    }
+}
+
+/// XORs `size_of::<T>()` bytes behind first pointer using key from second pointer,
+/// `W`-sized word at a time, writing each word with a volatile store.
+/// Provides no ordering guarantees by itself.
+///
+/// # Safety
+/// Same as [`xor_chunks`]; additionally, `T` must be at least as aligned as `W`
+/// (which makes `size_of::<T>()` a multiple of `size_of::<W>()`).
+unsafe fn xor_words<T, W: Copy + BitXor<Output = W>>(data: *mut u8, key: *const u8) {
+    debug_assert!(align_of::<T>() >= align_of::<W>() && size_of::<W>() == align_of::<W>());
+
+    let data = data.cast::<W>();
+    let key = key.cast::<W>();
+    for i in 0..size_of::<T>() / size_of::<W>() {
+        let data_word = unsafe { data.add(i).read() };
+        let key_word = unsafe { key.add(i).read() };
+        unsafe {
+            data.add(i).write_volatile(data_word ^ key_word);
+        }
+    }
+}
+
+/// Structure that handles remangling the pointed-to memory when dropped
+/// (both upon panic and successful completion of the unmangled scope).
+/// Its fields are private to this module because constructing it is
+/// only sound when the [`xor_chunks`] preconditions hold.
+struct RemangleGuard<T, S: MaskScheme = Xor> {
+    data: *mut u8,
+    key: *const u8,
+    token: PhantomData<(T, S)>,
+}
+
+impl<T, S: MaskScheme> Drop for RemangleGuard<T, S> {
+    fn drop(&mut self) {
+        let bomb = AbortOnUnwind("failed to remangle the contents");
+        unsafe { S::mask::<T>(self.data, self.key) }
+        bomb.defuse();
+    }
+}
+
+/// Counterpart of [`RemangleGuard`] for several boxes, remangling all of them before
+/// a single fence.
+struct RemangleBatchGuard<T> {
+    data: Vec<NonNull<T>>,
+    key: Vec<*const u8>,
+}
+
+impl<T> Drop for RemangleBatchGuard<T> {
+    fn drop(&mut self) {
+        let bomb = AbortOnUnwind("failed to remangle the contents");
+        for (data, key) in self.data.iter().zip(&self.key) {
+            unsafe { xor_chunks_nofence::<T>(data.as_ptr().cast::<u8>(), *key) }
+        }
+        fence(Ordering::SeqCst);
+        bomb.defuse();
     }
-    fence(Ordering::SeqCst);
 }
 
 /// Utility for masking a [`NoUninit`] structure in program's heap with
@@ -42,57 +171,533 @@ unsafe fn xor_chunks<T>(data: *mut u8, key: *const u8) {
 ///
 /// It is recommended to use [`std::clone::CloneToUninit`] to initialize
 /// the contents of the box, rather than constructing it on stack.
-pub struct MangledBox<T: NoUninit> {
+///
+/// # Thread safety
+/// [`Send`] is derived from `T` through the `data` and `key` fields; [`Sync`] is implemented
+/// manually with the same bound on `T`, so the box is shareable exactly when `T` is.
+/// Both the data and the key allocations are owned uniquely by the box.
+///
+/// Unmangling writes to the data allocation, so almost every method touching the contents
+/// takes `&mut self`. The one exception is [`MangledBox::with_unmangled_ref`], which
+/// claims an atomic in-use flag first: a shared access in progress on another thread is
+/// waited for, while one in progress on the same thread (reentrancy) is a panic before
+/// the data is touched. Without that flag, a shared `&MangledBox` cannot be used to
+/// unmangle from two threads at once:
+///
+/// ```compile_fail
+/// use secretmangle::MangledBox;
+///
+/// let secret = MangledBox::<u64>::new();
+/// let shared = &secret;
+/// std::thread::scope(|s| {
+///     s.spawn(|| shared.with_unmangled(|_| {}));
+///     s.spawn(|| shared.with_unmangled(|_| {}));
+/// });
+/// ```
+///
+/// # Types with invalid bit patterns
+/// [`NoUninit`] admits types such as `bool`, `char` or fieldless enums, not every bit pattern
+/// of which is a valid value. While masked, a byte of such a type may hold any of `0..=255`;
+/// that is fine, as the masked form is only ever stored as `MaybeUninit<T>` and handled as
+/// bytes, never as a `T`, so no `&T` to the masked data exists at any point.
+///
+/// [`MangledBox::with_unmangled`] completes the unmasking (fence included) before calling
+/// the closure, and the pointer it hands out is only meaningful inside the closure; once
+/// a valid value has been written, every read through that pointer sees a valid `T` again.
+/// A freshly constructed box, however, holds arbitrary bytes, so reading a `T` out of it
+/// before writing one is undefined behaviour for such types.
+///
+/// # Address stability
+/// The data lives in its own heap allocation, which stays in place when the box itself is
+/// moved, so the pointer handed out by [`MangledBox::with_unmangled`] is the same on every
+/// call until the box is dropped. The exceptions are [`MangledBox::rotate`] and
+/// [`MangledBox::compare_swap`], which exchange the allocations of two boxes. The box is
+/// [`Unpin`], and wrapping it in `Pin<Box<_>>` adds nothing; for contents that actually
+/// need pinning, see [`crate::MangledBoxArbitrary::with_unmangled_pinned`].
+///
+/// # Masking scheme
+/// The value is `data ^ key` by default; the third type parameter selects another
+/// [`MaskScheme`], e.g. [`AddMod256`](crate::AddMod256) for bytewise addition. Unmangling,
+/// rekeying and the methods built on them work under any scheme, while those relying on
+/// the XOR algebra (shares, masked-domain XOR and comparison, streaming and KDF rekeying,
+/// batched unmangling, [`MangledBox::copy_from`]) are only available with [`Xor`].
+///
+/// # Layout
+/// Both the data and the key live on the heap, so the size of the box does not depend on
+/// `T` and moving it never copies the contents. Besides the two pointers, the box holds
+/// non-secret metadata inline: the shared-access flag, the generation counter, the lazily
+/// drawn integrity-tag key and, with the `poison-on-panic` feature, the poison flag. With
+/// the global allocator this currently takes 56 bytes on 64-bit targets, or 64 bytes with
+/// `poison-on-panic`; the exact size is not a stability guarantee.
+///
+/// # Unwind safety
+/// The box is [`UnwindSafe`] and [`RefUnwindSafe`] regardless of `T` and `A`: whether
+/// a closure given to [`MangledBox::with_unmangled`] returns or panics, the contents are
+/// remangled before the unwind leaves the method, so no broken masking invariant can be
+/// observed past a [`std::panic::catch_unwind`] boundary, and a shared reference exposes
+/// nothing at all. Whatever the closure left in `T` itself stays the caller's concern.
+///
+/// A `&mut MangledBox` captured by the closure still needs [`std::panic::AssertUnwindSafe`],
+/// as `&mut` references are never [`UnwindSafe`].
+pub struct MangledBox<T: NoUninit, A: Allocator = Global, S: MaskScheme = Xor> {
     /// Heap allocation with bytes mangled by XORing with `key`.
     /// Each and every byte of the pointed-to value is initialized too.
-    data: Box<MaybeUninit<T>>,
+    /// Interior mutability is only used under the `shared_access` flag.
+    data: Box<UnsafeCell<MaybeUninit<T>>, A>,
 
-    /// T-sized buffer containing a cryptographically secure random key.
+    /// Heap allocation containing a cryptographically secure random key,
+    /// kept separate from `data` so that moving the box stays cheap.
     /// Each and every byte of the buffer is initialized.
-    key: MaybeUninit<T>,
+    key: Box<MaybeUninit<T>, A>,
+
+    /// Token of the thread in a method taking `&self` that reads or writes the data
+    /// allocation, zero if none; see [`SharedAccess`].
+    shared_access: AtomicUsize,
+
+    /// Non-secret count of values installed by [`MangledBox::compare_swap`] and
+    /// [`MangledBox::rotate`].
+    generation: u64,
+
+    /// SipHash key for [`MangledBox::integrity_tag`], drawn on first use.
+    tag_key: OnceLock<[u64; 2]>,
+
+    /// Set when a panic unwinds out of a closure given mutable access to the contents.
+    #[cfg(feature = "poison-on-panic")]
+    poisoned: AtomicBool,
+
+    /// Masking algebra, see [`MaskScheme`].
+    scheme: PhantomData<fn() -> S>,
+}
+
+/// Claim of a box's `shared_access` flag, released when dropped.
+struct SharedAccess<'a>(&'a AtomicUsize);
+
+impl<'a> SharedAccess<'a> {
+    /// Claims the flag, waiting for a claim held by another thread to be released.
+    ///
+    /// # Panics
+    /// Panics if the flag is already claimed by the current thread, i.e. on reentrancy,
+    /// which would otherwise wait forever.
+    fn claim(flag: &'a AtomicUsize) -> Self {
+        let token = Self::thread_token();
+        while let Err(owner) = flag.compare_exchange(0, token, Ordering::Acquire, Ordering::Relaxed) {
+            if owner == token {
+                panic!("contents are already being accessed through a shared reference");
+            }
+            std::thread::yield_now();
+        }
+        Self(flag)
+    }
+
+    /// Returns a nonzero token distinguishing the current thread from all other running
+    /// ones: the address of a thread-local.
+    fn thread_token() -> usize {
+        thread_local! {
+            static TOKEN: u8 = const { 0 };
+        }
+        TOKEN.with(|token| std::ptr::from_ref(token).addr())
+    }
+}
+
+impl Drop for SharedAccess<'_> {
+    fn drop(&mut self) {
+        self.0.store(0, Ordering::Release);
+    }
+}
+
+/// Sets a box's `poisoned` flag if dropped by a panic that started after its creation,
+/// as [`std::sync::Mutex`] guards do.
+#[cfg(feature = "poison-on-panic")]
+struct PoisonOnUnwind<'a> {
+    flag: &'a AtomicBool,
+    panicking: bool,
+}
+
+#[cfg(feature = "poison-on-panic")]
+impl<'a> PoisonOnUnwind<'a> {
+    fn arm(flag: &'a AtomicBool) -> Self {
+        Self { flag, panicking: std::thread::panicking() }
+    }
+}
+
+#[cfg(feature = "poison-on-panic")]
+impl Drop for PoisonOnUnwind<'_> {
+    fn drop(&mut self) {
+        if !self.panicking && std::thread::panicking() {
+            self.flag.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
+/// Error returned by [`MangledBox::try_with_unmangled`] for a box poisoned by a panic in
+/// an earlier unmangling closure; the contents may be left half-updated.
+#[cfg(feature = "poison-on-panic")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Poisoned;
+
+#[cfg(feature = "poison-on-panic")]
+impl std::fmt::Display for Poisoned {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "contents were poisoned by a panic during mutation")
+    }
+}
+
+#[cfg(feature = "poison-on-panic")]
+impl std::error::Error for Poisoned {}
+
 impl<T: NoUninit> MangledBox<T> {
     /// Constructs a new [`MangledBox`] with a random key and arbitrary data.
     pub fn new() -> Self {
-        let data = Box::new_zeroed();
+        Self::new_in(Global)
+    }
+
+    /// Constructs a new [`MangledBox`] like [`Self::new`], refusing at compile time types
+    /// not marked as plain data by [`Maskable`], such as raw pointers.
+    pub fn new_plain_data() -> Self
+    where
+        T: Maskable,
+    {
+        Self::new()
+    }
+
+    /// Constructs a new [`MangledBox`] holding a uniformly random value.
+    pub fn new_random() -> Self {
+        Self::new_random_in(Global)
+    }
+
+    /// Reconstructs a box from two shares produced by [`MangledBox::into_shares`], the
+    /// first becoming the masked data and the second the key; the value is their XOR.
+    ///
+    /// The masking is only as good as the shares: the second one is used as the key
+    /// as-is. Unless `T` is [`Pod`], the shares must XOR to a valid `T`.
+    ///
+    /// Shares are in the in-memory byte order of `T` and carry no endianness tag: shares
+    /// exported on a little-endian target reconstruct a different value of a multi-byte
+    /// type on a big-endian one. Persisting them across platforms requires agreeing on the
+    /// byte order (or using byte arrays as `T`).
+    ///
+    /// # Panics
+    /// Panics if either share is not exactly `size_of::<T>()` bytes long.
+    pub fn from_shares(a: &[u8], b: &[u8]) -> Self {
+        assert!(
+            a.len() == size_of::<T>() && b.len() == size_of::<T>(),
+            "shares of {} and {} bytes do not match {}-byte contents",
+            a.len(),
+            b.len(),
+            size_of::<T>()
+        );
+
+        let mut data = Box::new_uninit();
+        let mut key = Box::new_uninit();
+        data.as_bytes_mut().write_copy_of_slice(a);
+        key.as_bytes_mut().write_copy_of_slice(b);
+        // ^ both are fully initialized now, as the lengths match
+
+        // # Safety
+        // Every byte of both allocations was written above.
+        let this = unsafe { Self::from_parts(data, key) };
+        debug_assert!(this.check_invariants());
+        this
+    }
+}
+
+impl<const N: usize> MangledBox<[u8; N]>
+where
+    [u8; N]: NoUninit,
+{
+    /// Splits the box into two holding the first `M` and the remaining `R` bytes, e.g. to
+    /// separate two keys stored together. Both the masked data and the key are split, so
+    /// no plaintext is formed. `M + R` must equal `N`, which is checked at compile time:
+    ///
+    /// ```compile_fail
+    /// use secretmangle::MangledBox;
+    ///
+    /// let (a, b) = MangledBox::<[u8; 64]>::new().split_at::<32, 16>();
+    /// ```
+    pub fn split_at<const M: usize, const R: usize>(mut self) -> (MangledBox<[u8; M]>, MangledBox<[u8; R]>)
+    where
+        [u8; M]: NoUninit,
+        [u8; R]: NoUninit,
+    {
+        const { assert!(M + R == N, "split sizes must add up to the array length") };
+
+        // # Safety
+        // All bytes of the data and the key are initialized (type invariant).
+        let data = unsafe { self.data.get_mut().as_bytes().assume_init_ref() };
+        let key = unsafe { self.key.as_bytes().assume_init_ref() };
+        (
+            MangledBox::from_shares(&data[..M], &key[..M]),
+            MangledBox::from_shares(&data[M..], &key[M..]),
+        )
+    }
+
+    /// Unmangles the contents and invokes the provided closure on them viewed as a RustCrypto
+    /// [`GenericArray`](generic_array::GenericArray), e.g. a cipher key. Whether the closure
+    /// panics or returns normally, the contents are remangled.
+    ///
+    /// The length `L` must be `N`, which is checked at compile time.
+    #[cfg(feature = "rustcrypto")]
+    #[allow(deprecated, reason = "released RustCrypto crates still use generic-array 0.14")]
+    pub fn with_key_array<L, F, R>(&mut self, f: F) -> R
+    where
+        L: generic_array::ArrayLength<u8>,
+        F: FnOnce(&generic_array::GenericArray<u8, L>) -> R,
+    {
+        const { assert!(L::USIZE == N, "array length must match the box contents") };
+        // # Safety
+        // All bytes of the contents are initialized (type invariant).
+        self.with_unmangled(|p| f(generic_array::GenericArray::from_slice(unsafe { p.as_ref() })))
+    }
+}
+
+impl<const N: usize, A: Allocator> MangledBox<[u8; N], A>
+where
+    [u8; N]: NoUninit,
+{
+    /// XORs the value of `other` into this one, entirely in the masked domain: as the value
+    /// of a box is `data ^ key`, XORing both `other.data` and `other.key` into this data
+    /// makes its value `self ^ other`, keeping this box's key.
+    ///
+    /// Neither value appears unmasked at any point, which suits XOR secret sharing and
+    /// one-time-pad workflows.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on `other`.
+    pub fn xor_assign_masked<B: Allocator>(&mut self, other: &MangledBox<[u8; N], B>) {
+        let _other_access = SharedAccess::claim(&other.shared_access);
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+
+        // # Safety
+        // 1. All pointers point to `N`-byte allocations, all initialized (type invariant)
+        // 2. `&mut self` and `&other` cannot alias, so the data of this box overlaps neither
+        // 3. No one writes to `other.data` while its shared access is claimed
+        unsafe {
+            xor_chunks::<[u8; N]>(data_ptr, other.data.get().cast::<u8>());
+            xor_chunks::<[u8; N]>(data_ptr, Box::as_ptr(&other.key).cast::<u8>());
+        }
+    }
+}
+
+impl<const N: usize, A: Allocator, S: MaskScheme> MangledBox<[u8; N], A, S>
+where
+    [u8; N]: NoUninit,
+{
+    /// Unmangles the contents and replaces each byte with `f(index, byte)`, then remangles
+    /// them with the same key; call [`MangledBox::rekey`] afterwards for a fresh one.
+    ///
+    /// The transformation runs on plaintext, and the contents are remangled even if `f`
+    /// panics, leaving the bytes before the panicking index transformed.
+    pub fn map_bytes<F>(&mut self, f: F)
+    where
+        F: Fn(usize, u8) -> u8,
+    {
+        self.with_unmangled(|mut ptr| {
+            // # Safety
+            // The pointer is valid and unaliased for the duration of the closure, and any
+            // bytes are a valid `[u8; N]`.
+            let bytes = unsafe { ptr.as_mut() };
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = f(i, *byte);
+            }
+        });
+    }
+}
+
+impl<T: NoUninit, A: Allocator + Clone, S: MaskScheme> MangledBox<T, A, S> {
+    /// Constructs a new [`MangledBox`] with a random key and arbitrary data,
+    /// placing both the data and the key in the provided allocator.
+    pub fn new_in(alloc: A) -> Self {
+        let mut key = Box::new_uninit_in(alloc.clone());
+        keygen::fill(key.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key`] is fully initialized
+        // # Safety
+        // [`key`] is fully initialized, see above.
+        unsafe { keygen::audit_key(&*key) };
+
+        let data = unsafe { Box::new_zeroed_in(alloc).assume_init() };
         // ^ [`data`] starts with arbitrary data from perspective of outer
         //   program; therefore we may choose anything, including that the block
         //   might had data equal to key (their XOR being zero).
+        //   Zeroes are a valid `UnsafeCell<MaybeUninit<T>>` as any bytes are.
+
+        Self {
+            data,
+            key,
+            shared_access: AtomicUsize::new(0),
+            generation: 0,
+            tag_key: OnceLock::new(),
+            #[cfg(feature = "poison-on-panic")]
+            poisoned: AtomicBool::new(false),
+            scheme: PhantomData,
+        }
+    }
+
+    /// Constructs a new [`MangledBox`] holding a uniformly random value, placing both
+    /// the data and the key in the provided allocator.
+    ///
+    /// The data and the key are filled with independent random bytes, so their XOR is
+    /// uniformly random too and the value is never present unmasked, e.g. for generating
+    /// session keys. Unless `T` is [`Pod`], the value need not be a valid `T`.
+    pub fn new_random_in(alloc: A) -> Self {
+        let mut this = Self::new_in(alloc);
+        keygen::fill(this.data.get_mut().as_bytes_mut());
+        // ^ keygen::fill guarantees that [`data`] is fully initialized
+        this
+    }
+}
+
+impl<T: NoUninit, A: Allocator, S: MaskScheme> MangledBox<T, A, S> {
 
-        let mut key = MaybeUninit::uninit();
-        getrandom::fill_uninit(key.as_bytes_mut()).expect("no keygen");
-        // ^ fill_uninit guarantees that [`key`] is fully initialized on success
+    /// Returns the length of the key in bytes, which is `size_of::<T>()`.
+    ///
+    /// This is non-secret metadata and does not touch the masked contents.
+    pub fn key_len(&self) -> usize {
+        size_of::<T>()
+    }
+
+    /// Returns the size of the masked value in bytes, which is `size_of::<T>()`.
+    ///
+    /// This is non-secret metadata and does not touch the masked contents.
+    pub fn len_bytes(&self) -> usize {
+        size_of::<T>()
+    }
+
+    /// Returns whether the masked value is zero-sized, i.e. there is nothing to mask.
+    pub fn is_empty(&self) -> bool {
+        size_of::<T>() == 0
+    }
+
+    /// Checks the structural invariants of the box: the data and the key are distinct,
+    /// non-overlapping `size_of::<T>()`-byte allocations, both aligned for `T`. Returns
+    /// `false` on any violation.
+    ///
+    /// Every box built through this crate's API satisfies them, so this is a cheap hook for
+    /// fuzzing harnesses and import paths to assert on; it touches no secret bytes.
+    pub fn check_invariants(&self) -> bool {
+        let data = self.data.get().addr();
+        let key = Box::as_ptr(&self.key).addr();
+        let len = size_of::<T>();
 
-        Self { data, key }
+        let aligned = data.is_multiple_of(align_of::<T>()) && key.is_multiple_of(align_of::<T>());
+        let disjoint = len == 0 || data + len <= key || key + len <= data;
+        aligned && disjoint && data != 0 && key != 0
     }
 
     /// Rekeys the box, preserving its contents.
+    ///
+    /// The random differential is drawn on the stack, or into a heap buffer for contents
+    /// larger than 4 KiB, so that rekeying megabyte-sized secrets cannot overflow the stack.
     pub fn rekey(&mut self) {
-        let mut diff_key = MaybeUninit::<T>::uninit();
-        getrandom::fill_uninit(diff_key.as_bytes_mut()).expect("no keygen");
+        with_diff_scratch(|diff_key: &mut MaybeUninit<T>| {
+            keygen::fill(diff_key.as_bytes_mut());
+            // ^ keygen::fill guarantees that [`diff_key`] is fully initialized
+
+            unsafe { self.rekey_with(diff_key) }
+        })
+    }
 
+    /// Rekeys the box by masking both the data and the key with `diff_key`.
+    ///
+    /// # Safety
+    /// Every byte of `diff_key` must be initialized.
+    unsafe fn rekey_with(&mut self, diff_key: &MaybeUninit<T>) {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Rekey, size_of::<T>());
         unsafe {
-            xor_chunks::<T>(
+            S::mask::<T>(
                 Box::as_mut_ptr(&mut self.data).cast::<u8>(),
                 diff_key.as_ptr().cast::<u8>(),
             );
-            xor_chunks::<T>(
-                self.key.as_mut_ptr().cast::<u8>(),
+            S::mask::<T>(
+                Box::as_mut_ptr(&mut self.key).cast::<u8>(),
                 diff_key.as_ptr().cast::<u8>(),
             );
+            // All bytes of the key are initialized (type invariant).
+            keygen::audit_key(&*self.key);
         }
     }
 
+    /// Installs `replacement` in place of this box and returns the previous box, e.g. for
+    /// archival during key rotation. Advances the generation.
+    ///
+    /// Only the data and key allocations change hands, so neither secret is unmasked.
+    pub fn rotate(&mut self, replacement: Self) -> Self {
+        let generation = self.generation;
+        let old = std::mem::replace(self, replacement);
+        self.generation = generation.wrapping_add(1);
+        old
+    }
+
+    /// Returns the generation of the box: how many values were installed into it by
+    /// [`MangledBox::compare_swap`] and [`MangledBox::rotate`]. This is non-secret metadata.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Installs `new` and returns the previous box, as [`MangledBox::rotate`] does, if the
+    /// generation is still `expected_gen`; otherwise returns `Err` with the current one.
+    ///
+    /// This gives optimistic concurrency for secret rotation: a task preparing a new
+    /// secret for the generation it observed cannot overwrite one installed meanwhile.
+    /// The caller still synchronizes around `&mut self`; neither secret is unmasked.
+    pub fn compare_swap(&mut self, expected_gen: u64, new: Self) -> Result<Self, u64> {
+        if self.generation != expected_gen {
+            return Err(self.generation);
+        }
+        Ok(self.rotate(new))
+    }
+
+    /// Like [`MangledBox::with_unmangled`], but returns [`Poisoned`] without unmangling
+    /// anything if a panic unwound out of an earlier closure given mutable access to the
+    /// contents, by this method, [`MangledBox::with_unmangled`] or
+    /// [`MangledBox::overwrite_by_ptr`] (and the helpers built on them, such as
+    /// [`with_two`]). [`MangledBox::with_unmangled_batched`] does not poison.
+    ///
+    /// As with [`std::sync::Mutex`], poisoning only flags a possibly half-updated value;
+    /// the masking itself is restored either way. [`MangledBox::with_unmangled`] ignores
+    /// the flag.
+    #[cfg(feature = "poison-on-panic")]
+    pub fn try_with_unmangled<F, R>(&mut self, f: F) -> Result<R, Poisoned>
+    where
+        F: FnOnce(NonNull<T>) -> R,
+    {
+        if self.is_poisoned() {
+            return Err(Poisoned);
+        }
+        Ok(self.with_unmangled(f))
+    }
+
+    /// Returns whether the box is poisoned, see [`MangledBox::try_with_unmangled`].
+    ///
+    /// This is non-secret metadata and does not touch the masked contents.
+    #[cfg(feature = "poison-on-panic")]
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the poisoned flag, e.g. after restoring the contents to a consistent value.
+    #[cfg(feature = "poison-on-panic")]
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
     /// Unmangles the contents and invokes the provided closure on it.
     /// Whether the closure panics or returns normally, the contents
     /// are remangled.
+    ///
+    /// The contents are fully unmasked before the closure is called; see "Types with invalid
+    /// bit patterns" in the type documentation for when reading a `T` through the pointer
+    /// is sound.
     pub fn with_unmangled<F, R>(&mut self, f: F) -> R
     where
         F: FnOnce(NonNull<T>) -> R,
     {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+        #[cfg(feature = "poison-on-panic")]
+        let _poison = PoisonOnUnwind::arm(&self.poisoned);
         let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
-        let key_ptr = self.key.as_ptr().cast::<u8>();
+        let key_ptr = Box::as_ptr(&self.key).cast::<u8>();
 
         // Never panics as that's a pointer into Box allocation.
         // Compiler is probably able to optimize this check out.
@@ -106,24 +711,10 @@ impl<T: NoUninit> MangledBox<T> {
         // 3. [`key_ptr`], obtained from `&MaybeUninit<T>`, points
         //    to an allocation of at least `size_of::<T>()`.
         //    Our type invariant guarantees that all bytes are init too
-        // 4. [`data_ptr`] points to heap allocation and [`key_ptr`] to
-        //    stack, therefore they do not overlap.
+        // 4. [`data_ptr`] and [`key_ptr`] point to two distinct heap
+        //    allocations, therefore they do not overlap.
         unsafe {
-            xor_chunks::<T>(data_ptr, key_ptr);
-        }
-
-        /// Structure that handles remangling the pointed-to memory when
-        /// dropped (both upon panic and successful [`with_unmangled`]
-        /// completion). It is scoped because it is unsafe to construct.
-        struct RemangleGuard<T> {
-            data: *mut u8,
-            key: *const u8,
-            token: PhantomData<T>,
-        }
-        impl<T> Drop for RemangleGuard<T> {
-            fn drop(&mut self) {
-                unsafe { xor_chunks::<T>(self.data, self.key) }
-            }
+            S::unmask::<T>(data_ptr, key_ptr);
         }
 
         // # Safety
@@ -134,9 +725,9 @@ impl<T: NoUninit> MangledBox<T> {
         // 3. [`key_ptr`], obtained from `&MaybeUninit<T>`, points
         //    to an allocation of at least `size_of::<T>()`.
         //    Our type invariant guarantees that all bytes are init too
-        // 4. [`data_ptr`] points to heap allocation and [`key_ptr`] to
-        //    stack, therefore they do not overlap.
-        let _guard = RemangleGuard::<T> {
+        // 4. [`data_ptr`] and [`key_ptr`] point to two distinct heap
+        //    allocations, therefore they do not overlap.
+        let _guard = RemangleGuard::<T, S> {
             data: data_ptr,
             key: key_ptr,
             token: PhantomData,
@@ -144,77 +735,859 @@ impl<T: NoUninit> MangledBox<T> {
 
         f(data_nn.cast())
     }
-}
 
-impl<T: NoUninit> Default for MangledBox<T> {
-    fn default() -> Self {
-        Self::new()
+    /// Unmangles the contents and invokes the provided closure on it like
+    /// [`MangledBox::with_unmangled`], reporting an overrun if the closure runs longer than
+    /// `max`, e.g. because of an accidental blocking call while the secret is unmasked.
+    ///
+    /// The closure is not interrupted; the overrun is reported once it returns or panics,
+    /// before the contents are remangled, to the handler installed with
+    /// [`crate::timing::set_overrun_handler`] or else to standard error. The measured
+    /// duration is non-secret metadata.
+    pub fn with_unmangled_timed<F, R>(&mut self, max: Duration, f: F) -> R
+    where
+        F: FnOnce(NonNull<T>) -> R,
+    {
+        self.with_unmangled(|ptr| {
+            let _check = OverrunCheck::start(max, size_of::<T>());
+            f(ptr)
+        })
     }
-}
 
-impl<T: NoUninit> Drop for MangledBox<T> {
-    fn drop(&mut self) {
-        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
-        let key_ptr = self.key.as_mut_ptr().cast::<u8>();
+    /// Copies the unmangled contents into the start of `dst` and returns the number of bytes
+    /// written, `size_of::<T>()`; the box itself is remangled before returning.
+    ///
+    /// This is the explicit bridge for FFI calls that need a real plaintext buffer rather
+    /// than a pointer borrowed for the duration of [`MangledBox::with_unmangled`]. The
+    /// plaintext then lives in `dst`, and **zeroing it afterwards is up to the caller**.
+    ///
+    /// # Panics
+    /// Panics without unmangling anything if `dst` is shorter than `size_of::<T>()`.
+    pub fn copy_unmasked_into(&mut self, dst: &mut [u8]) -> usize {
+        let len = size_of::<T>();
+        assert!(dst.len() >= len, "destination buffer too short: {} < {len}", dst.len());
+        self.with_unmangled(|p| {
+            // # Safety
+            // `p` points to `len` initialized bytes, the contents being `NoUninit`; `dst` is a
+            // distinct allocation, as it can't borrow from `&mut self`.
+            let plain = unsafe { std::slice::from_raw_parts(p.as_ptr().cast::<u8>(), len) };
+            dst[..len].copy_from_slice(plain);
+        });
+        len
+    }
 
+    /// Unmangles the contents and invokes the provided closure on a reference to them,
+    /// returning the owned value it extracts, e.g. a single field or a derived scalar.
+    /// Whether the closure panics or returns normally, the contents are remangled.
+    ///
+    /// The `R: NoUninit` bound makes explicit that only copyable plain data may leave the
+    /// closure, never a borrow of the contents, which would point to remasked garbage:
+    ///
+    /// ```compile_fail
+    /// use secretmangle::MangledBox;
+    ///
+    /// let mut secret = MangledBox::<[u8; 4]>::new();
+    /// let leaked: &[u8; 4] = secret.with_unmangled_copy_out(|value| value);
+    /// ```
+    ///
+    /// This requires `T: Pod`, as the box cannot know whether a valid value was written.
+    pub fn with_unmangled_copy_out<F, R>(&mut self, f: F) -> R
+    where
+        T: Pod,
+        F: FnOnce(&T) -> R,
+        R: NoUninit,
+    {
         // # Safety
-        // 1. Both pointers point to some `MaybeUninit<T>`, so aligned
-        // 2. Both pointers were obtained from `&mut MaybeUninit<T>`
-        //    to an allocation of at least `size_of::<T>()`.
-        //    Our type invariant guarantees that all bytes are init too
-        // 3. (2) implies that read is safe too.
-        // 4. Each call passes the same pointer in both arguments.
+        // All bytes of the contents are initialized (type invariant), and any initialized
+        // bit pattern is a valid `T: Pod`; the reference does not outlive the closure call.
+        self.with_unmangled(|p| f(unsafe { p.as_ref() }))
+    }
+
+    /// Installs `new` as the contents and returns the previous value, like
+    /// [`std::mem::replace`]. Whether reading or writing panics, the contents are remangled.
+    ///
+    /// This requires `T: Pod`, as the box cannot know whether a valid value was written
+    /// before. Both the old and the new value are plaintext on the stack for the duration
+    /// of the swap, which is inherent to exchanging values by copy.
+    pub fn replace(&mut self, new: T) -> T
+    where
+        T: Pod,
+    {
+        // # Safety
+        // All bytes of the contents are initialized (type invariant), and any initialized
+        // bit pattern is a valid `T: Pod`.
+        self.with_unmangled(|p| unsafe { p.replace(new) })
+    }
+
+    /// Feeds the unmangled contents to `state` as bytes, remangling them afterwards.
+    ///
+    /// The hash is necessarily computed over the plaintext, so that it matches the hash of
+    /// a looked-up value; the plaintext only exists for the duration of the call. Hashers
+    /// process their whole input, so the timing depends on the size of `T` only, but the
+    /// resulting hash is derived from the secret: with a low-entropy secret and an unkeyed
+    /// hasher, it allows a brute-force search. Prefer a randomly keyed hasher such as the
+    /// default [`RandomState`]. See [`ByValue`] for a wrapper implementing [`Hash`].
+    pub fn hash_unmasked<H: Hasher>(&mut self, state: &mut H) {
+        let len = size_of::<T>();
+        self.with_unmangled(|p| {
+            // # Safety
+            // `p` points to `len` initialized bytes, the contents being `NoUninit`.
+            state.write(unsafe { std::slice::from_raw_parts(p.as_ptr().cast::<u8>(), len) });
+        });
+    }
+
+    /// Compares the contents against `candidate` in constant time, without copying
+    /// the unmangled contents anywhere.
+    ///
+    /// Every byte is compared, with no early exit. A `candidate` of the wrong length yields
+    /// `Choice(0)`, after a dummy pass comparing the contents with themselves so that
+    /// the timing barely depends on the length.
+    #[cfg(feature = "subtle")]
+    pub fn ct_eq_plaintext(&mut self, candidate: &[u8]) -> subtle::Choice {
+        use subtle::ConstantTimeEq;
+
+        self.with_unmangled(|p| {
+            // # Safety
+            // All bytes of the contents are initialized (type invariant).
+            let stored = unsafe { std::slice::from_raw_parts(p.as_ptr().cast::<u8>(), size_of::<T>()) };
+
+            let len_ok = (candidate.len() as u64).ct_eq(&(stored.len() as u64));
+            let other = if candidate.len() == stored.len() { candidate } else { stored };
+            let bytes_ok = stored
+                .iter()
+                .zip(other)
+                .fold(subtle::Choice::from(1), |acc, (a, b)| acc & a.ct_eq(b));
+            len_ok & bytes_ok
+        })
+    }
+
+    /// Unmangles the contents and invokes the provided closure on a shared reference to
+    /// them, through a shared reference to the box. Whether the closure panics or returns
+    /// normally, the contents are remangled.
+    ///
+    /// This requires `T: Pod`, as the box cannot know whether a valid value was written.
+    ///
+    /// Concurrent calls from several threads are serialized: each waits for the shared
+    /// access in progress to end before unmangling.
+    ///
+    /// # Panics
+    /// Panics without touching the contents if called from inside the closure, or from
+    /// any other shared access to the same box in progress on the current thread.
+    pub fn with_unmangled_ref<F, R>(&self, f: F) -> R
+    where
+        T: Pod,
+        F: FnOnce(&T) -> R,
+    {
+        let _access = SharedAccess::claim(&self.shared_access);
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+
+        let data_ptr = self.data.get().cast::<u8>();
+        let key_ptr = Box::as_ptr(&self.key).cast::<u8>();
+
+        // # Safety
+        // As in [`Self::with_unmangled`]; the data is behind an `UnsafeCell` and no other
+        // access to it can happen while [`_access`] is held, as all the others take
+        // `&mut self` or claim the flag too.
         unsafe {
-            xor_chunks::<T>(data_ptr, data_ptr);
-            xor_chunks::<T>(key_ptr, key_ptr);
+            S::unmask::<T>(data_ptr, key_ptr);
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // # Safety
+        // Same as above. The guard is dropped before [`_access`].
+        let _guard = RemangleGuard::<T, S> {
+            data: data_ptr,
+            key: key_ptr,
+            token: PhantomData,
+        };
 
-    fn ensure_send<T: Send>(_v: &T) {}
-    fn ensure_sync<T: Sync>(_v: &T) {}
+        // # Safety
+        // All bytes are initialized (type invariant) and valid for `T: Pod`; the reference
+        // does not outlive the closure call, which ends before the remangling.
+        f(unsafe { &*data_ptr.cast::<T>() })
+    }
 
-    #[test]
-    fn zst() {
-        let mut empty_box = MangledBox::<()>::new();
-        ensure_send(&empty_box);
-        ensure_sync(&empty_box);
+    /// Invokes the provided closure on the masked contents viewed as 64-bit words, e.g. to
+    /// compute a tamper-detection tag over the masked form. Nothing is unmasked.
+    ///
+    /// Returns [`None`] without calling the closure unless `T` is at least 8-aligned and its
+    /// size is a multiple of 8.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on the same box.
+    pub fn with_masked_words<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u64]) -> R,
+    {
+        if align_of::<T>() < align_of::<u64>() || !size_of::<T>().is_multiple_of(size_of::<u64>()) {
+            return None;
+        }
+        let _access = SharedAccess::claim(&self.shared_access);
 
-        empty_box.with_unmangled(|_| {});
+        // # Safety
+        // 1. The data is aligned for `T`, hence for `u64` as checked above
+        // 2. All `size_of::<T>()` bytes are initialized (type invariant), and no one
+        //    writes to them while [`_access`] is held
+        let words = unsafe {
+            std::slice::from_raw_parts(
+                self.data.get().cast::<u64>(),
+                size_of::<T>() / size_of::<u64>(),
+            )
+        };
+        Some(f(words))
     }
 
-    #[derive(bytemuck::NoUninit, Clone, Copy)]
-    #[repr(C, align(64))]
-    struct Align64;
+    /// Computes a tag over the masked data and the key, to be stored by the caller and
+    /// checked with [`MangledBox::verify_tag`] before trusting a later unmangle.
+    ///
+    /// The tag is a SipHash-2-4 under a 128-bit key drawn from the entropy source on the
+    /// box's first call, covering both halves, so corruption of either one is detected.
+    /// Nothing is unmasked. Rekeying changes both halves, and
+    /// writing through [`MangledBox::with_unmangled`] may change the data, so the tag must
+    /// be recomputed after either.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on the same box.
+    pub fn integrity_tag(&self) -> u64 {
+        let _access = SharedAccess::claim(&self.shared_access);
 
-    #[test]
-    fn overaligned_zst() {
-        let mut align64_box = MangledBox::<Align64>::new();
-        ensure_send(&align64_box);
-        ensure_sync(&align64_box);
+        // # Safety
+        // All bytes of the data and the key are initialized (type invariant), and no one
+        // writes to the data while [`_access`] is held.
+        let data = unsafe { (*self.data.get()).as_bytes().assume_init_ref() };
+        let key = unsafe { self.key.as_bytes().assume_init_ref() };
 
-        align64_box.with_unmangled(|p| {
-            assert_eq!(
-                p.as_ptr().align_offset(64),
-                0,
-                "alignment not preserved on overaligned ZST type"
-            );
+        let [k0, k1] = *self.tag_key.get_or_init(|| {
+            let mut tag_key = [MaybeUninit::uninit(); 16];
+            let tag_key = keygen::fill(&mut tag_key);
+            [0, 8].map(|i| u64::from_ne_bytes(tag_key[i..i + 8].try_into().unwrap()))
         });
+        // `SipHasher` is deprecated only in favour of `DefaultHasher`, which does not
+        // guarantee a particular algorithm.
+        #[allow(deprecated)]
+        let mut hasher = std::hash::SipHasher::new_with_keys(k0, k1);
+        hasher.write(data);
+        hasher.write(key);
+        hasher.finish()
     }
 
-    // This MangledBox depends on NoUninit trait which requires Copy.
-    // Therefore, it trivially invokes no data destructors - we cannot
-    // statically fit a value with Drop implementation.
+    /// Checks that the masked data and the key are unchanged since `tag` was computed by
+    /// [`MangledBox::integrity_tag`].
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on the same box.
+    pub fn verify_tag(&self, tag: u64) -> bool {
+        self.integrity_tag() == tag
+    }
 
-    #[test]
-    fn data_u8_preserved() {
-        let mut box_ = MangledBox::<u8>::new();
-        box_.with_unmangled(|p| unsafe { p.write(42) });
-        box_.with_unmangled(|p| {
+    /// Replaces the contents with a value constructed in place by the provided closure.
+    ///
+    /// The old masked bytes are zeroed and the key is redrawn before the closure runs, so
+    /// the old value is destroyed before any byte of the new one is written, and the two
+    /// never coexist unmasked. The closure receives a pointer to zeroed memory; whether it
+    /// panics or returns normally, the contents are masked with the fresh key afterwards.
+    pub fn overwrite_by_ptr<F>(&mut self, f: F)
+    where
+        F: FnOnce(NonNull<T>),
+    {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+        #[cfg(feature = "poison-on-panic")]
+        let _poison = PoisonOnUnwind::arm(&self.poisoned);
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+
+        // # Safety
+        // [`data_ptr`] is aligned and points to `size_of::<T>()` initialized bytes
+        // (type invariant); passing it twice zeroes them.
+        unsafe {
+            xor_chunks::<T>(data_ptr, data_ptr);
+        }
+        keygen::fill(self.key.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key`] is fully initialized
+        // # Safety
+        // [`key`] is fully initialized, see above.
+        unsafe { keygen::audit_key(&*self.key) };
+
+        let data_nn: NonNull<u8> = NonNull::new(data_ptr).unwrap();
+
+        // # Safety
+        // Same as in [`Self::with_unmangled`]; the data stays initialized as it was
+        // zeroed above and `T: NoUninit` writes cannot introduce uninit bytes.
+        let _guard = RemangleGuard::<T, S> {
+            data: data_ptr,
+            key: Box::as_ptr(&self.key).cast::<u8>(),
+            token: PhantomData,
+        };
+
+        f(data_nn.cast())
+    }
+}
+
+// Operations relying on the XOR algebra, see "Masking scheme" in the type documentation.
+impl<T: NoUninit, A: Allocator> MangledBox<T, A> {
+    /// Rekeys the box like [`MangledBox::rekey`], but drawing the differential 64 bytes at
+    /// a time and applying each chunk to both the data and the key before drawing the next.
+    ///
+    /// The stack then only ever holds a 64-byte buffer, wiped before returning, instead of
+    /// a whole `T`-sized differential, which matters for large secrets.
+    pub fn rekey_streaming(&mut self) {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Rekey, size_of::<T>());
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+        let key_ptr = Box::as_mut_ptr(&mut self.key).cast::<u8>();
+
+        let mut chunk = [MaybeUninit::<u8>::uninit(); 64];
+        for start in (0..size_of::<T>()).step_by(chunk.len()) {
+            let len = chunk.len().min(size_of::<T>() - start);
+            let diff = keygen::fill(&mut chunk[..len]);
+            // ^ keygen::fill guarantees that [`diff`] is fully initialized
+
+            for (i, &diff) in diff.iter().enumerate() {
+                // # Safety
+                // Both pointers point to distinct allocations of `size_of::<T>()` initialized
+                // bytes (type invariant), and `start + i < size_of::<T>()`.
+                unsafe {
+                    let data = data_ptr.add(start + i);
+                    data.write_volatile(data.read() ^ diff);
+                    let key = key_ptr.add(start + i);
+                    key.write_volatile(key.read() ^ diff);
+                }
+            }
+        }
+        wipe_bytes(&mut chunk);
+        // # Safety
+        // All bytes of the key are initialized (type invariant).
+        unsafe { keygen::audit_key(&*self.key) };
+    }
+
+    /// Rekeys the box with a differential derived by HKDF-SHA256 from the current key, fresh
+    /// entropy and `context`, preserving its contents.
+    ///
+    /// After `fork()`, the parent and the child hold the same masked bytes and key. Calling
+    /// this in each with distinct contexts (e.g. their PIDs) makes their key material diverge,
+    /// even if the entropy source itself was duplicated across the fork.
+    #[cfg(feature = "kdf-reseed")]
+    pub fn reseed_from_kdf(&mut self, context: &[u8]) {
+        /// Most output a single HKDF-SHA256 expansion may produce.
+        const MAX_EXPAND: usize = 255 * 32;
+
+        let mut salt = [MaybeUninit::uninit(); 32];
+        keygen::fill(&mut salt);
+        // ^ keygen::fill guarantees that [`salt`] is fully initialized
+
+        // # Safety
+        // All bytes of the key and the salt are initialized (type invariant, see above).
+        let key = unsafe { self.key.as_bytes().assume_init_ref() };
+        let salt_bytes = unsafe { salt.assume_init_ref() };
+        // Kept in a `MaybeUninit`, so that the pseudorandom key it holds can be wiped in
+        // place and is never moved or dropped; it owns no other resources.
+        let mut hkdf = MaybeUninit::new(hkdf::Hkdf::<sha2::Sha256>::new(Some(salt_bytes), key));
+        // # Safety
+        // Initialized just above.
+        let hkdf_ref = unsafe { hkdf.assume_init_ref() };
+
+        with_diff_scratch(|diff_key: &mut MaybeUninit<T>| {
+            // # Safety
+            // All bytes of [`diff_key`] were zeroed, hence are initialized.
+            let okm = unsafe { diff_key.as_bytes_mut().assume_init_mut() };
+            for (block, chunk) in okm.chunks_mut(MAX_EXPAND).enumerate() {
+                hkdf_ref.expand_multi_info(&[context, &(block as u64).to_le_bytes()], chunk)
+                    .expect("chunk fits in a single expansion");
+            }
+
+            // # Safety
+            // [`diff_key`] is fully initialized, see above.
+            unsafe { self.rekey_with(diff_key) }
+        });
+        // ^ with_diff_scratch wipes the differential
+
+        wipe_bytes(hkdf.as_bytes_mut());
+        wipe_bytes(&mut salt);
+    }
+
+    /// Splits the box into two shares which XOR to the value: the masked data and the key.
+    ///
+    /// This is a 2-of-2 XOR secret sharing: each share alone is uniformly random and reveals
+    /// nothing about the value. The value is never unmasked; the box is wiped afterwards.
+    /// The shares are in the in-memory (native-endian) byte order of `T`; see
+    /// [`MangledBox::from_shares`] for the cross-platform implications.
+    pub fn into_shares(mut self) -> (Box<[u8]>, Box<[u8]>) {
+        // # Safety
+        // All bytes of the data and the key are initialized (type invariant).
+        let data = unsafe { self.data.get_mut().as_bytes().assume_init_ref() };
+        let key = unsafe { self.key.as_bytes().assume_init_ref() };
+        (data.into(), key.into())
+    }
+
+    /// Copies the masked data and the key into a [`MaskedSnapshot`], e.g. for serialization,
+    /// without unmasking anything.
+    ///
+    /// The copied pair is always consistent: rekeying requires `&mut self`, so it cannot run
+    /// concurrently with this, and the shared-access flag excludes
+    /// [`MangledBox::with_unmangled_ref`], the one method changing the data behind `&self`.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on the same box.
+    pub fn snapshot_masked(&self) -> MaskedSnapshot {
+        let _access = SharedAccess::claim(&self.shared_access);
+        // # Safety
+        // All bytes of the data and the key are initialized (type invariant), and no one
+        // writes to the data while the shared access is claimed.
+        let data = unsafe { (*self.data.get()).as_bytes().assume_init_ref() };
+        let key = unsafe { self.key.as_bytes().assume_init_ref() };
+        MaskedSnapshot { data: data.into(), key: key.into() }
+    }
+
+    /// Makes the contents of this box equal to those of `src`, keeping this box's key.
+    ///
+    /// The masked bytes of `src` are copied over and then re-masked from `src`'s key
+    /// to this box's key, XORing this key in before taking the source key out,
+    /// so at no point do the plaintext bytes appear in memory.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on `src`.
+    pub fn copy_from<B: Allocator>(&mut self, src: &MangledBox<T, B>) {
+        let _src_access = SharedAccess::claim(&src.shared_access);
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+
+        // # Safety
+        // 1. All pointers point to some `MaybeUninit<T>`, so aligned, and to allocations
+        //    of at least `size_of::<T>()` bytes, all initialized (type invariant)
+        // 2. `&mut self` and `&src` cannot alias, so every pair points to two
+        //    distinct heap allocations
+        // 3. No one writes to `src.data` while its shared access is claimed
+        // 4. After the copy [`data_ptr`] holds initialized bytes again
+        unsafe {
+            std::ptr::copy_nonoverlapping(src.data.get(), self.data.get_mut(), 1);
+            xor_chunks::<T>(data_ptr, Box::as_ptr(&self.key).cast::<u8>());
+            xor_chunks::<T>(data_ptr, Box::as_ptr(&src.key).cast::<u8>());
+        }
+    }
+
+    /// Unmangles the contents of all `boxes` and invokes the provided closure on pointers
+    /// to them, in the same order. Whether the closure panics or returns normally, all the
+    /// contents are remangled.
+    ///
+    /// This is a performance option for bulk operations on many small boxes: unmangling
+    /// and remangling emit one fence for the whole batch instead of one per box. Hence
+    /// the XORs within a batch are not ordered against each other, and the caller must
+    /// not rely on any ordering between them; only the contents being remasked before
+    /// anything following the call is guaranteed, as with [`MangledBox::with_unmangled`].
+    pub fn with_unmangled_batched<F, R>(boxes: &mut [Self], f: F) -> R
+    where
+        F: FnOnce(&[NonNull<T>]) -> R,
+    {
+        let mut guard = RemangleBatchGuard::<T> {
+            data: Vec::with_capacity(boxes.len()),
+            key: Vec::with_capacity(boxes.len()),
+        };
+        for box_ in boxes.iter_mut() {
+            #[cfg(feature = "telemetry")]
+            crate::telemetry::record(crate::telemetry::AccessKind::Unmangle, size_of::<T>());
+
+            let data_ptr = Box::as_mut_ptr(&mut box_.data).cast::<u8>();
+            let key_ptr = Box::as_ptr(&box_.key).cast::<u8>();
+
+            // # Safety
+            // As in [`Self::with_unmangled`] for each box; distinct boxes own distinct
+            // allocations. The pointer is pushed to the guard right away, so that every
+            // unmangled box is remangled even if a later push panics.
+            unsafe {
+                xor_chunks_nofence::<T>(data_ptr, key_ptr);
+            }
+            guard.data.push(NonNull::new(data_ptr).unwrap().cast());
+            guard.key.push(key_ptr);
+        }
+        fence(Ordering::SeqCst);
+
+        f(&guard.data)
+    }
+
+    /// Compares the contents with those of `other` in constant time, without unmasking
+    /// either of them.
+    ///
+    /// A single fused loop computes `(data ^ other.data) ^ (key ^ other.key)` for each byte,
+    /// which is zero exactly where the values agree, and ORs it into an accumulator; the
+    /// plaintext of neither box is formed, not even in registers, nor stored anywhere.
+    ///
+    /// # Panics
+    /// Panics if called from inside [`MangledBox::with_unmangled_ref`] on either box.
+    #[cfg(feature = "subtle")]
+    pub fn ct_eq<B: Allocator>(&self, other: &MangledBox<T, B>) -> subtle::Choice {
+        use subtle::ConstantTimeEq;
+
+        if std::ptr::addr_eq(self, other) {
+            return subtle::Choice::from(1);
+        }
+        // Claimed in address order, so that two threads comparing the same pair of boxes
+        // in opposite directions do not wait for each other forever.
+        let in_order = std::ptr::from_ref(&self.shared_access) < std::ptr::from_ref(&other.shared_access);
+        let (_access, _other_access) = if in_order {
+            let access = SharedAccess::claim(&self.shared_access);
+            (access, SharedAccess::claim(&other.shared_access))
+        } else {
+            let other_access = SharedAccess::claim(&other.shared_access);
+            (SharedAccess::claim(&self.shared_access), other_access)
+        };
+
+        // # Safety
+        // All bytes of the data and the keys are initialized (type invariant), and no one
+        // writes to the data while [`_access`] and [`_other_access`] are held.
+        let (data, key, other_data, other_key) = unsafe {
+            (
+                (*self.data.get()).as_bytes().assume_init_ref(),
+                self.key.as_bytes().assume_init_ref(),
+                (*other.data.get()).as_bytes().assume_init_ref(),
+                other.key.as_bytes().assume_init_ref(),
+            )
+        };
+
+        let mut diff = 0u8;
+        for i in 0..size_of::<T>() {
+            diff |= (data[i] ^ other_data[i]) ^ (key[i] ^ other_key[i]);
+        }
+        std::hint::black_box(diff).ct_eq(&0)
+    }
+}
+
+/// Rekeys every box in the slice, preserving their contents, with all diff keys taken
+/// from a single entropy draw instead of one per box.
+///
+/// The diff keys are wiped before their buffer is freed, as together with either the old
+/// or the new keys they would reveal the other.
+pub fn rekey_all<T: NoUninit, A: Allocator, S: MaskScheme>(boxes: &mut [MangledBox<T, A, S>]) {
+    let mut diff_keys = Box::<[T]>::new_uninit_slice(boxes.len());
+    keygen::fill(diff_keys.as_bytes_mut());
+    // ^ keygen::fill guarantees that [`diff_keys`] is fully initialized
+
+    for (box_, diff_key) in boxes.iter_mut().zip(diff_keys.iter()) {
+        unsafe { box_.rekey_with(diff_key) }
+    }
+
+    wipe_bytes(diff_keys.as_bytes_mut());
+}
+
+/// Unmangles two boxes at once and invokes the provided closure on both contents, e.g.
+/// for mixing two secrets. Whether the closure panics or returns normally, both boxes
+/// are remangled. The boxes may use different masking schemes.
+///
+/// The second box is only unmangled once the first one's remangle guard is in place,
+/// so a panic at any point, including while unmangling the second box, remangles
+/// whichever contents are exposed at that moment.
+pub fn with_two<T: Pod, U: Pod, A: Allocator, B: Allocator, SA: MaskScheme, SB: MaskScheme, R>(
+    a: &mut MangledBox<T, A, SA>,
+    b: &mut MangledBox<U, B, SB>,
+    f: impl FnOnce(&mut T, &mut U) -> R,
+) -> R {
+    // # Safety
+    // All bytes of the contents are initialized (type invariant), and any initialized
+    // bit pattern is a valid `T: Pod` or `U: Pod`. The two boxes are distinct as both
+    // are borrowed mutably.
+    a.with_unmangled(|mut pa| {
+        b.with_unmangled(|mut pb| f(unsafe { pa.as_mut() }, unsafe { pb.as_mut() }))
+    })
+}
+
+impl<T: NoUninit, A: Allocator, S: MaskScheme> MangledBox<T, A, S> {
+    /// Zeroes both the data and the key, leaving the box holding zero.
+    ///
+    /// With the `sentinel-wipe` feature, both are filled with the sentinel byte instead,
+    /// which leaves the box holding zero as well.
+    pub(crate) fn wipe(&mut self) {
+        let data_ptr = Box::as_mut_ptr(&mut self.data).cast::<u8>();
+        let key_ptr = Box::as_mut_ptr(&mut self.key).cast::<u8>();
+
+        // # Safety
+        // 1. Both pointers point to some `MaybeUninit<T>`, so aligned
+        // 2. Both pointers were obtained from `&mut MaybeUninit<T>`
+        //    to an allocation of at least `size_of::<T>()`.
+        //    Our type invariant guarantees that all bytes are init too
+        // 3. (2) implies that read is safe too.
+        // 4. Each call passes the same pointer in both arguments.
+        #[cfg(not(feature = "sentinel-wipe"))]
+        unsafe {
+            xor_chunks::<T>(data_ptr, data_ptr);
+            xor_chunks::<T>(key_ptr, key_ptr);
+        }
+
+        // # Safety
+        // Both pointers are valid for writes of `size_of::<T>()` bytes, see above.
+        #[cfg(feature = "sentinel-wipe")]
+        unsafe {
+            crate::sentinel::fill(data_ptr, size_of::<T>());
+            crate::sentinel::fill(key_ptr, size_of::<T>());
+        }
+    }
+}
+
+impl<T: NoUninit, A: Allocator> MangledBox<T, A> {
+    /// Takes the box apart into the masked data and the key, without wiping them.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(crate) fn into_parts(self) -> (Box<MaybeUninit<T>, A>, Box<MaybeUninit<T>, A>) {
+        let this = std::mem::ManuallyDrop::new(self);
+        // # Safety
+        // [`this`] is never used or dropped again, so both boxes are moved out exactly once
+        // and the remaining fields need no dropping; `UnsafeCell` is `repr(transparent)`.
+        unsafe {
+            let (data, alloc) = Box::into_raw_with_allocator(std::ptr::read(&this.data));
+            let data = Box::from_raw_in(data.cast::<MaybeUninit<T>>(), alloc);
+            (data, std::ptr::read(&this.key))
+        }
+    }
+
+    /// Reassembles a box from the masked data and the key.
+    ///
+    /// # Safety
+    /// Every byte of both allocations must be initialized.
+    pub(crate) unsafe fn from_parts(data: Box<MaybeUninit<T>, A>, key: Box<MaybeUninit<T>, A>) -> Self {
+        let (data, alloc) = Box::into_raw_with_allocator(data);
+        Self {
+            data: unsafe { Box::from_raw_in(data.cast::<UnsafeCell<MaybeUninit<T>>>(), alloc) },
+            key,
+            shared_access: AtomicUsize::new(0),
+            generation: 0,
+            tag_key: OnceLock::new(),
+            #[cfg(feature = "poison-on-panic")]
+            poisoned: AtomicBool::new(false),
+            scheme: PhantomData,
+        }
+    }
+
+    /// XORs `mask` into the key but not the data, for containers keeping the key itself
+    /// masked; the contents only read back correctly once `mask` is XORed out again.
+    ///
+    /// # Safety
+    /// Every byte of `mask` must be initialized.
+    pub(crate) unsafe fn xor_key(&mut self, mask: &MaybeUninit<T>) {
+        // # Safety
+        // As in [`Self::rekey_with`], for the key alone.
+        unsafe {
+            xor_chunks::<T>(Box::as_mut_ptr(&mut self.key).cast::<u8>(), mask.as_ptr().cast::<u8>());
+        }
+    }
+
+    /// XORs `mask` out of the key, invokes the closure on the box and XORs `mask` back in,
+    /// whether the closure panics or returns normally.
+    ///
+    /// # Safety
+    /// Every byte of `mask` must be initialized, and the closure must not move the key
+    /// allocation out of the box (as [`MangledBox::rotate`] would).
+    pub(crate) unsafe fn with_key_unmasked<F, R>(&mut self, mask: &MaybeUninit<T>, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        unsafe { self.xor_key(mask) }
+
+        // # Safety
+        // Both pointers point to `MaybeUninit<T>` allocations, all initialized, with [`mask`]
+        // borrowed for the whole call; the key stays in place as the caller guarantees.
+        let _guard = RemangleGuard::<T> {
+            data: Box::as_mut_ptr(&mut self.key).cast::<u8>(),
+            key: mask.as_ptr().cast::<u8>(),
+            token: PhantomData,
+        };
+        f(self)
+    }
+
+    /// Brings a wiped box to the state of a newly constructed one: arbitrary contents
+    /// under a fresh key, at generation zero, not poisoned and with no integrity-tag key.
+    pub(crate) fn reinit(&mut self) {
+        keygen::fill(self.key.as_bytes_mut());
+        // ^ keygen::fill guarantees that [`key`] is fully initialized
+        // # Safety
+        // [`key`] is fully initialized, see above.
+        unsafe { keygen::audit_key(&*self.key) };
+        self.generation = 0;
+        self.tag_key = OnceLock::new();
+        #[cfg(feature = "poison-on-panic")]
+        self.clear_poison();
+    }
+}
+
+impl<T: NoUninit> Default for MangledBox<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// # Safety
+// See "Thread safety" in the type documentation: the only interior mutability is guarded
+// by the `shared_access` flag.
+unsafe impl<T: NoUninit + Sync, A: Allocator + Sync, S: MaskScheme> Sync for MangledBox<T, A, S> {}
+
+// See "Unwind safety" in the type documentation.
+impl<T: NoUninit, A: Allocator, S: MaskScheme> UnwindSafe for MangledBox<T, A, S> {}
+impl<T: NoUninit, A: Allocator, S: MaskScheme> RefUnwindSafe for MangledBox<T, A, S> {}
+
+impl<T: Pod, A: Allocator, S: MaskScheme> SecretContainer for MangledBox<T, A, S> {
+    type Value = T;
+
+    fn rekey(&mut self) {
+        MangledBox::rekey(self);
+    }
+
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        // # Safety
+        // All bytes of the contents are initialized (type invariant), and any initialized
+        // bit pattern is a valid `T: Pod`.
+        Some(self.with_unmangled(|mut p| f(unsafe { p.as_mut() })))
+    }
+}
+
+/// Consistent copy of a [`MangledBox`]'s masked data and key, taken by
+/// [`MangledBox::snapshot_masked`]. Both copies are zeroed when the snapshot is dropped.
+///
+/// Together the two shares reveal the secret, so a snapshot deserves the same care as
+/// the plaintext; they should be stored or transmitted separately where possible.
+pub struct MaskedSnapshot {
+    data: Box<[u8]>,
+    key: Box<[u8]>,
+}
+
+impl MaskedSnapshot {
+    /// Returns the masked data and the key, in the byte order described at
+    /// [`MangledBox::from_shares`].
+    pub fn shares(&self) -> (&[u8], &[u8]) {
+        (&self.data, &self.key)
+    }
+
+    /// Reconstructs a box from the snapshot.
+    ///
+    /// # Panics
+    /// Panics if the snapshot was not taken of a box with `size_of::<T>()`-byte contents.
+    pub fn restore<T: NoUninit>(&self) -> MangledBox<T> {
+        MangledBox::from_shares(&self.data, &self.key)
+    }
+}
+
+impl Drop for MaskedSnapshot {
+    fn drop(&mut self) {
+        for byte in self.data.iter_mut().chain(self.key.iter_mut()) {
+            // # Safety
+            // The pointer comes from a live `&mut u8`.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Wrapper comparing and hashing a [`MangledBox`] by its unmangled contents, so that masked
+/// secrets can be stored in a [`HashSet`](std::collections::HashSet) or used as map keys.
+///
+/// Hashing goes through [`MangledBox::hash_unmasked`]'s byte feed, and its timing
+/// considerations apply. Equality compares every byte, with no early exit. Both unmangle
+/// through [`MangledBox::with_unmangled_ref`], so they panic if called while the box is
+/// being accessed that way already.
+///
+/// The box's interior mutability only serves such accesses and always leaves the contents
+/// as they were, so Clippy's `mutable_key_type` lint is a false positive for this type.
+pub struct ByValue<T: Pod, A: Allocator = Global>(pub MangledBox<T, A>);
+
+impl<T: Pod, A: Allocator> Hash for ByValue<T, A> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.with_unmangled_ref(|value| state.write(bytemuck::bytes_of(value)));
+    }
+}
+
+impl<T: Pod, A: Allocator> PartialEq for ByValue<T, A> {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            // Claiming the same box twice would panic.
+            return true;
+        }
+        self.0.with_unmangled_ref(|a| {
+            other.0.with_unmangled_ref(|b| {
+                let (a, b) = (bytemuck::bytes_of(a), bytemuck::bytes_of(b));
+                a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+            })
+        })
+    }
+}
+
+impl<T: Pod, A: Allocator> Eq for ByValue<T, A> {}
+
+impl<T: NoUninit, A: Allocator, S: MaskScheme> Drop for MangledBox<T, A, S> {
+    fn drop(&mut self) {
+        #[cfg(feature = "telemetry")]
+        crate::telemetry::record(crate::telemetry::AccessKind::Drop, size_of::<T>());
+        keygen::audit_forget(&*self.key);
+        self.wipe();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{AllocError, Layout};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::num::Wrapping;
+    use std::sync::atomic::AtomicUsize;
+    use std::hash::{BuildHasher, RandomState};
+
+    use super::*;
+
+    fn ensure_send<T: Send>(_v: &T) {}
+    fn ensure_sync<T: Sync>(_v: &T) {}
+
+    #[test]
+    fn zst() {
+        let mut empty_box = MangledBox::<()>::new();
+        ensure_send(&empty_box);
+        ensure_sync(&empty_box);
+
+        empty_box.with_unmangled(|_| {});
+    }
+
+    #[test]
+    fn zst_draws_no_entropy() {
+        let draws = keygen::draws();
+        let mut empty_box = MangledBox::<()>::new();
+        empty_box.rekey();
+        empty_box.rekey_streaming();
+        empty_box.with_unmangled(|_| {});
+        drop(empty_box);
+        drop(MangledBox::<()>::new_random());
+        assert_eq!(keygen::draws(), draws);
+
+        drop(MangledBox::<u8>::new());
+        assert_eq!(keygen::draws(), draws + 1);
+    }
+
+    #[derive(bytemuck::NoUninit, Clone, Copy)]
+    #[repr(C, align(64))]
+    struct Align64;
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn size_independent_of_contents() {
+        let expected = if cfg!(feature = "poison-on-panic") { 64 } else { 56 };
+        assert_eq!(size_of::<MangledBox<u8>>(), expected, "update the layout documentation");
+        assert_eq!(size_of::<MangledBox<[[u8; 4096]; 256]>>(), expected);
+    }
+
+    #[test]
+    fn overaligned_zst() {
+        let mut align64_box = MangledBox::<Align64>::new();
+        ensure_send(&align64_box);
+        ensure_sync(&align64_box);
+
+        align64_box.with_unmangled(|p| {
+            assert_eq!(
+                p.as_ptr().align_offset(64),
+                0,
+                "alignment not preserved on overaligned ZST type"
+            );
+        });
+    }
+
+    // This MangledBox depends on NoUninit trait which requires Copy.
+    // Therefore, it trivially invokes no data destructors - we cannot
+    // statically fit a value with Drop implementation.
+
+    #[test]
+    fn data_u8_preserved() {
+        let mut box_ = MangledBox::<u8>::new();
+        box_.with_unmangled(|p| unsafe { p.write(42) });
+        box_.with_unmangled(|p| {
             assert_eq!(unsafe { p.read() }, 42);
         });
         box_.rekey();
@@ -226,6 +1599,397 @@ mod tests {
         });
     }
 
+    #[test]
+    fn panic_remangles() {
+        const PATTERN: [u8; 32] = [0x5A; 32];
+
+        let mut box_ = MangledBox::<[u8; 32]>::new();
+        box_.with_unmangled(|p| unsafe { p.write(PATTERN) });
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            box_.with_unmangled(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+
+        let masked = unsafe { box_.data.get_mut().assume_init_read() };
+        assert_ne!(masked, PATTERN, "contents left unmasked after panic");
+        box_.with_unmangled(|p| {
+            assert_eq!(unsafe { p.read() }, PATTERN);
+        });
+    }
+
+    #[test]
+    fn overwrite_by_ptr() {
+        let mut box_ = MangledBox::<u64>::new();
+        box_.with_unmangled(|p| unsafe { p.write(5) });
+        let old_key = unsafe { box_.key.assume_init_read() };
+
+        box_.overwrite_by_ptr(|p| {
+            assert_eq!(unsafe { p.read() }, 0, "old value was not destroyed");
+            unsafe { p.write(9) };
+        });
+        assert_ne!(unsafe { box_.key.assume_init_read() }, old_key, "key was not redrawn");
+        box_.with_unmangled(|p| {
+            assert_eq!(unsafe { p.read() }, 9);
+        });
+    }
+
+    #[test]
+    fn copy_from_keeps_own_key() {
+        let mut staging = MangledBox::<[u32; 5]>::new();
+        staging.with_unmangled(|p| unsafe { p.write([1, 2, 3, 4, 5]) });
+        let mut live = MangledBox::<[u32; 5]>::new();
+        let live_key = unsafe { live.key.assume_init_read() };
+
+        live.copy_from(&staging);
+        assert_eq!(unsafe { live.key.assume_init_read() }, live_key);
+        live.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3, 4, 5]));
+        staging.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn with_two_mixes_and_remangles() {
+        let mut a = MangledBox::<[u8; 4]>::new();
+        let mut b = MangledBox::<u32>::new();
+        a.with_unmangled(|p| unsafe { p.write([1, 2, 3, 4]) });
+        b.with_unmangled(|p| unsafe { p.write(0x01010101) });
+
+        let sum = with_two(&mut a, &mut b, |a, b| {
+            *b ^= u32::from_ne_bytes(*a);
+            a.iter().map(|&x| x as u32).sum::<u32>()
+        });
+        assert_eq!(sum, 10);
+        let expected = u32::from_ne_bytes([1, 2, 3, 4]) ^ 0x01010101;
+        b.with_unmangled(|p| assert_eq!(unsafe { p.read() }, expected));
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            with_two(&mut a, &mut b, |_, _| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+        assert_ne!(unsafe { a.data.get_mut().assume_init_read() }, [1, 2, 3, 4], "first box left unmasked");
+        a.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3, 4]));
+    }
+
+    #[cfg(feature = "subtle")]
+    #[test]
+    fn ct_eq_plaintext() {
+        let mut box_ = MangledBox::<[u8; 6]>::new();
+        box_.with_unmangled(|p| unsafe { p.write(*b"hunter") });
+
+        assert!(bool::from(box_.ct_eq_plaintext(b"hunter")));
+        assert!(!bool::from(box_.ct_eq_plaintext(b"hunted")));
+        assert!(!bool::from(box_.ct_eq_plaintext(b"hunter2")));
+        assert!(!bool::from(box_.ct_eq_plaintext(b"")));
+        assert_ne!(unsafe { box_.data.get_mut().assume_init_read() }, *b"hunter", "contents left unmasked");
+    }
+
+    #[test]
+    fn rekey_all_preserves_contents() {
+        let mut boxes: Vec<_> = (0..5u64)
+            .map(|i| {
+                let mut box_ = MangledBox::<[u64; 3]>::new();
+                box_.with_unmangled(|p| unsafe { p.write([i; 3]) });
+                box_
+            })
+            .collect();
+        let old_keys: Vec<_> = boxes.iter().map(|b| unsafe { b.key.assume_init_read() }).collect();
+
+        rekey_all(&mut boxes);
+        for (i, box_) in boxes.iter_mut().enumerate() {
+            assert_ne!(unsafe { box_.key.assume_init_read() }, old_keys[i], "box {i} not rekeyed");
+            box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [i as u64; 3]));
+        }
+        rekey_all::<u8, Global, Xor>(&mut []);
+    }
+
+    #[test]
+    fn with_unmangled_ref_reads() {
+        let mut box_ = MangledBox::<[u16; 3]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3]) });
+        ensure_sync(&box_);
+
+        let shared = &box_;
+        assert_eq!(shared.with_unmangled_ref(|v| v.iter().sum::<u16>()), 6);
+        let mut copy = MangledBox::<[u16; 3]>::new();
+        copy.copy_from(shared);
+        copy.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1, 2, 3]));
+    }
+
+    #[test]
+    fn with_unmangled_ref_reentrancy_panics() {
+        let mut box_ = MangledBox::<u32>::new();
+        box_.with_unmangled(|p| unsafe { p.write(0xC0FFEE) });
+
+        let shared = &box_;
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            shared.with_unmangled_ref(|_| shared.with_unmangled_ref(|v| *v))
+        }));
+        assert!(result.is_err());
+        assert_ne!(unsafe { box_.data.get_mut().assume_init_read() }, 0xC0FFEE, "contents left unmasked");
+        assert_eq!(box_.with_unmangled_ref(|v| *v), 0xC0FFEE, "flag not released after panic");
+    }
+
+    #[test]
+    fn with_unmangled_ref_concurrent_readers_wait() {
+        let mut box_ = MangledBox::<[u64; 4]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3, 4]) });
+
+        let shared = &box_;
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        assert_eq!(shared.with_unmangled_ref(|v| v.iter().sum::<u64>()), 10);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn reinit_redraws_tag_key() {
+        let mut box_ = MangledBox::<[u8; 16]>::new();
+        box_.integrity_tag();
+        assert!(box_.tag_key.get().is_some());
+
+        box_.wipe();
+        box_.reinit();
+        assert!(box_.tag_key.get().is_none(), "tag key carried over to the next owner");
+        let tag = box_.integrity_tag();
+        assert!(box_.verify_tag(tag));
+    }
+
+    #[test]
+    fn new_random_differs() {
+        let mut a = MangledBox::<[u8; 32]>::new_random();
+        let mut b = MangledBox::<[u8; 32]>::new_random();
+        let a_value = a.with_unmangled(|p| unsafe { p.read() });
+        let b_value = b.with_unmangled(|p| unsafe { p.read() });
+        assert_ne!(a_value, [0; 32]);
+        assert_ne!(a_value, b_value);
+        a.rekey();
+        a.with_unmangled(|p| assert_eq!(unsafe { p.read() }, a_value));
+    }
+
+    #[test]
+    fn compare_swap_generations() {
+        let make = |value: u64| {
+            let mut box_ = MangledBox::<u64>::new();
+            box_.with_unmangled(|p| unsafe { p.write(value) });
+            box_
+        };
+
+        let mut live = make(1);
+        assert_eq!(live.generation(), 0);
+        let mut old = live.compare_swap(0, make(2)).ok().unwrap();
+        old.with_unmangled(|p| assert_eq!(unsafe { p.read() }, 1));
+        assert_eq!(live.generation(), 1);
+
+        assert_eq!(live.compare_swap(0, make(3)).err(), Some(1));
+        live.with_unmangled(|p| assert_eq!(unsafe { p.read() }, 2));
+
+        live.rotate(make(4));
+        assert_eq!(live.generation(), 2);
+    }
+
+    #[test]
+    fn with_unmangled_batched() {
+        let mut boxes: Vec<_> = (0..6u32)
+            .map(|i| {
+                let mut box_ = MangledBox::<u32>::new();
+                box_.with_unmangled(|p| unsafe { p.write(i) });
+                box_
+            })
+            .collect();
+
+        let sum = MangledBox::with_unmangled_batched(&mut boxes, |ptrs| {
+            ptrs.iter().map(|p| unsafe { p.read() }).sum::<u32>()
+        });
+        assert_eq!(sum, 15);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            MangledBox::with_unmangled_batched(&mut boxes, |ptrs| {
+                unsafe { ptrs[5].write(50) };
+                panic!("closure failed");
+            });
+        }));
+        assert!(result.is_err());
+        for (i, box_) in boxes.iter_mut().enumerate() {
+            let expected = if i == 5 { 50 } else { i as u32 };
+            assert_ne!(unsafe { box_.data.get_mut().assume_init_read() }, expected, "box {i} left unmasked");
+            box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, expected));
+        }
+    }
+
+    #[test]
+    fn shares_round_trip() {
+        let mut box_ = MangledBox::<[u32; 3]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([10, 20, 30]) });
+
+        let (a, b) = box_.into_shares();
+        assert_eq!(a.len(), 12);
+        let joined: Vec<u8> = a.iter().zip(&b).map(|(x, y)| x ^ y).collect();
+        assert_eq!(joined, bytemuck::bytes_of(&[10u32, 20, 30]));
+
+        let draws = keygen::draws();
+        let mut restored = MangledBox::<[u32; 3]>::from_shares(&a, &b);
+        assert_eq!(keygen::draws(), draws, "the shares are the key, nothing to draw");
+        restored.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [10, 20, 30]));
+
+        let result = catch_unwind(|| MangledBox::<[u32; 3]>::from_shares(&a[1..], &b));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn with_masked_words() {
+        let mut box_ = MangledBox::<[u64; 2]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 2]) });
+        let key = unsafe { box_.key.assume_init_read() };
+
+        let unmasked = box_.with_masked_words(|w| [w[0] ^ key[0], w[1] ^ key[1]]);
+        assert_eq!(unmasked, Some([1, 2]));
+        assert_eq!(MangledBox::<[u32; 3]>::new().with_masked_words(|_| ()), None);
+        assert_eq!(MangledBox::<[u8; 8]>::new().with_masked_words(|_| ()), None);
+    }
+
+    #[test]
+    fn integrity_tag_detects_corruption() {
+        let mut box_ = MangledBox::<[u8; 24]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([3; 24]) });
+        let tag = box_.integrity_tag();
+        assert!(box_.verify_tag(tag));
+
+        box_.with_unmangled(|_| {});
+        assert!(box_.verify_tag(tag), "read-only access changed the tag");
+
+        let flip = |bytes: &mut [MaybeUninit<u8>], i: usize| {
+            let byte = unsafe { bytes[i].assume_init() };
+            bytes[i].write(!byte);
+        };
+        flip(box_.data.get_mut().as_bytes_mut(), 5);
+        assert!(!box_.verify_tag(tag), "data corruption not detected");
+        flip(box_.data.get_mut().as_bytes_mut(), 5);
+        assert!(box_.verify_tag(tag));
+        flip(box_.key.as_bytes_mut(), 23);
+        assert!(!box_.verify_tag(tag), "key corruption not detected");
+    }
+
+    #[test]
+    fn shares_are_native_endian() {
+        let mut box_ = MangledBox::<u32>::new();
+        box_.with_unmangled(|p| unsafe { p.write(0xEEDDCCBB) });
+
+        let (a, b) = box_.into_shares();
+        let joined: Vec<u8> = a.iter().zip(&b).map(|(x, y)| x ^ y).collect();
+        assert_eq!(joined, 0xEEDDCCBBu32.to_ne_bytes());
+
+        let mut restored = MangledBox::<u32>::from_shares(&a, &b);
+        restored.with_unmangled(|p| assert_eq!(unsafe { p.read() }, 0xEEDDCCBB));
+    }
+
+    #[test]
+    fn split_at() {
+        let mut joined = MangledBox::<[u8; 64]>::new();
+        joined.with_unmangled(|p| unsafe {
+            p.cast::<[u8; 32]>().write([1; 32]);
+            p.cast::<[u8; 32]>().add(1).write([2; 32]);
+        });
+
+        let (mut first, mut second) = joined.split_at::<32, 32>();
+        first.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1; 32]));
+        second.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [2; 32]));
+
+        let (mut head, mut rest) = first.split_at::<0, 32>();
+        head.with_unmangled(|p| assert_eq!(unsafe { p.read() }, []));
+        rest.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [1; 32]));
+    }
+
+    #[cfg(feature = "subtle")]
+    #[test]
+    fn ct_eq_masked() {
+        let make = |value: [u8; 16]| {
+            let mut box_ = MangledBox::<[u8; 16]>::new();
+            box_.with_unmangled(|p| unsafe { p.write(value) });
+            box_
+        };
+
+        let a = make([4; 16]);
+        let mut b = make([4; 16]);
+        assert!(bool::from(a.ct_eq(&b)));
+        assert!(bool::from(a.ct_eq(&a)));
+        b.rekey();
+        assert!(bool::from(a.ct_eq(&b)));
+
+        let mut c = [4; 16];
+        c[15] = 5;
+        assert!(!bool::from(a.ct_eq(&make(c))));
+    }
+
+    #[cfg(feature = "rustcrypto")]
+    #[test]
+    #[allow(deprecated, reason = "released RustCrypto crates still use generic-array 0.14")]
+    fn with_key_array() {
+        use generic_array::typenum::{U16, U32};
+
+        let mut key = MangledBox::<[u8; 32]>::new();
+        key.with_unmangled(|p| unsafe { p.write([0x42; 32]) });
+        assert_eq!(key.with_key_array::<U32, _, _>(|k| k.as_slice().to_vec()), [0x42; 32]);
+
+        let mut short = MangledBox::<[u8; 16]>::new_random();
+        let value = short.with_unmangled(|p| unsafe { p.read() });
+        short.with_key_array::<U16, _, _>(|k| assert_eq!(k.as_slice(), value));
+    }
+
+    fn round_trip<T: NoUninit + PartialEq + std::fmt::Debug>(value: T) {
+        let mut box_ = MangledBox::<T>::new();
+        box_.with_unmangled(|p| unsafe { p.write(value) });
+        box_.rekey();
+        box_.with_unmangled(|p| {
+            assert_eq!(unsafe { p.read() }, value);
+        });
+    }
+
+    #[test]
+    fn data_small_types_preserved() {
+        round_trip(0xA5u8);
+        round_trip(0xBEEFu16);
+        round_trip(0xDEADBEEFu32);
+        round_trip(0x0123456789ABCDEFu64);
+        round_trip(u128::MAX / 3);
+        round_trip([1u8, 2, 3]);
+        round_trip([0x1111u16, 0x2222, 0x3333]);
+        round_trip([0x11111111u32, 0x22222222, 0x33333333, 0x44444444]);
+        round_trip([7u8; 16]);
+        round_trip([9u8; 17]);
+    }
+
+    #[test]
+    fn key_len() {
+        assert_eq!(MangledBox::<()>::new().key_len(), 0);
+        assert_eq!(MangledBox::<u32>::new().key_len(), 4);
+        assert_eq!(MangledBox::<[u64; 5]>::new().key_len(), 40);
+    }
+
+    #[test]
+    fn rotate_generations() {
+        let make = |value: u64| {
+            let mut box_ = MangledBox::<u64>::new();
+            box_.with_unmangled(|p| unsafe { p.write(value) });
+            box_
+        };
+
+        let mut live = make(0);
+        let mut archive = Vec::new();
+        for generation in 1..=4 {
+            archive.push(live.rotate(make(generation)));
+        }
+
+        live.with_unmangled(|p| assert_eq!(unsafe { p.read() }, 4));
+        for (generation, old) in archive.iter_mut().enumerate() {
+            old.with_unmangled(|p| assert_eq!(unsafe { p.read() }, generation as u64));
+        }
+    }
+
     #[test]
     fn data_u64_preserved() {
         let mut box_ = MangledBox::<u64>::new();
@@ -236,4 +2000,306 @@ mod tests {
             assert_eq!(unsafe { p.read() }, pattern);
         });
     }
+
+    /// Allocator counting its live allocations.
+    struct CountingAlloc(AtomicUsize);
+
+    unsafe impl Allocator for &CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn custom_allocator() {
+        let alloc = CountingAlloc(AtomicUsize::new(0));
+        {
+            let mut box_ = MangledBox::<u64, _>::new_in(&alloc);
+            assert_eq!(alloc.0.load(Ordering::SeqCst), 2);
+
+            box_.with_unmangled(|p| unsafe { p.write(7) });
+            box_.rekey();
+            box_.with_unmangled(|p| {
+                assert_eq!(unsafe { p.read() }, 7);
+            });
+        }
+        assert_eq!(alloc.0.load(Ordering::SeqCst), 0, "data or key allocation leaked");
+    }
+
+    #[test]
+    fn copy_unmasked_into_buffer() {
+        let mut box_ = MangledBox::<[u8; 4]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([9, 8, 7, 6]) });
+        let masked = unsafe { box_.data.get_mut().assume_init_read() };
+
+        let mut dst = [0u8; 6];
+        assert_eq!(box_.copy_unmasked_into(&mut dst), 4);
+        assert_eq!(dst, [9, 8, 7, 6, 0, 0]);
+        assert_eq!(unsafe { box_.data.get_mut().assume_init_read() }, masked);
+    }
+
+    #[test]
+    #[should_panic(expected = "too short")]
+    fn copy_unmasked_into_short_buffer() {
+        MangledBox::<u64>::new().copy_unmasked_into(&mut [0u8; 7]);
+    }
+
+    #[test]
+    fn bool_roundtrip_with_any_masked_byte() {
+        let mut masked_bytes = std::collections::HashSet::new();
+        for i in 0..64 {
+            let mut box_ = MangledBox::<bool>::new();
+            box_.with_unmangled(|p| unsafe { p.write(i % 2 == 0) });
+            masked_bytes.insert(unsafe { box_.data.get_mut().as_bytes()[0].assume_init() });
+            box_.rekey();
+            box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, i % 2 == 0));
+        }
+        // All 64 masked bytes being 0 or 1 has probability 2^-448.
+        assert!(masked_bytes.iter().any(|&b| b > 1), "masked bool bytes look unmasked");
+    }
+
+    #[test]
+    fn new_plain_data_accepts_maskable() {
+        let mut box_ = MangledBox::<[Wrapping<u32>; 4]>::new_plain_data();
+        box_.with_unmangled(|p| unsafe { p.write([Wrapping(1); 4]) });
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [Wrapping(1); 4]));
+    }
+
+    #[test]
+    #[cfg(feature = "kdf-reseed")]
+    fn reseed_from_kdf_diverges_copies() {
+        type Big = [[u8; 4096]; 3];
+        let mut original = MangledBox::<Big>::new();
+        original.with_unmangled(|p| unsafe { p.write([[0x42; 4096]; 3]) });
+        let (data, key) = original.into_shares();
+        let mut parent = MangledBox::<Big>::from_shares(&data, &key);
+        let mut child = MangledBox::<Big>::from_shares(&data, &key);
+
+        parent.reseed_from_kdf(b"pid 100");
+        child.reseed_from_kdf(b"pid 101");
+        let parent_key = bytemuck::bytes_of(unsafe { parent.key.assume_init_ref() }).to_vec();
+        let child_key = bytemuck::bytes_of(unsafe { child.key.assume_init_ref() }).to_vec();
+        assert_ne!(parent_key, child_key);
+        // Past the first HKDF expansion too.
+        assert_ne!(parent_key[9000..], key[9000..]);
+
+        parent.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [[0x42; 4096]; 3]));
+        child.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [[0x42; 4096]; 3]));
+    }
+
+    #[test]
+    #[cfg(feature = "kdf-reseed")]
+    #[cfg_attr(miri, ignore = "too slow under miri")]
+    fn reseed_from_kdf_large_on_small_stack() {
+        // A 4 MiB differential overflows the 1 MiB stack unless it is built on the heap.
+        type Huge = [[[u8; 4096]; 256]; 4];
+        std::thread::Builder::new()
+            .stack_size(1 << 20)
+            .spawn(|| {
+                let mut box_ = MangledBox::<Huge>::new();
+                box_.with_unmangled(|p| unsafe { p.cast::<u8>().add(3 << 20).write(0x5a) });
+                box_.reseed_from_kdf(b"ctx");
+                box_.with_unmangled(|p| assert_eq!(unsafe { p.cast::<u8>().add(3 << 20).read() }, 0x5a));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn rekey_streaming_preserves_contents() {
+        // 220 bytes: three full 64-byte chunks and a partial one.
+        let mut box_ = MangledBox::<[[u8; 20]; 11]>::new();
+        let value = std::array::from_fn(|i| [i as u8; 20]);
+        box_.with_unmangled(|p| unsafe { p.write(value) });
+        let key_before = unsafe { box_.key.assume_init_read() };
+        box_.rekey_streaming();
+        let key_after = unsafe { box_.key.assume_init_read() };
+        assert_ne!(key_before[0], key_after[0]);
+        assert_ne!(key_before[10], key_after[10], "tail past the last full chunk left unkeyed");
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, value));
+
+        let mut empty = MangledBox::<()>::new();
+        empty.rekey_streaming();
+    }
+
+    #[test]
+    #[allow(clippy::mutable_key_type, reason = "see the `ByValue` documentation")]
+    fn hash_set_by_value() {
+        let token = |v: u64| {
+            let mut box_ = MangledBox::<u64>::new();
+            box_.with_unmangled(|p| unsafe { p.write(v) });
+            box_
+        };
+        let mut bad_tokens = std::collections::HashSet::new();
+        assert!(bad_tokens.insert(ByValue(token(1))));
+        assert!(bad_tokens.insert(ByValue(token(2))));
+        assert!(!bad_tokens.insert(ByValue(token(1))), "equal secrets under distinct keys");
+        assert!(bad_tokens.contains(&ByValue(token(2))));
+        assert!(!bad_tokens.contains(&ByValue(token(3))));
+
+        let state = RandomState::new();
+        let mut a = token(5);
+        let mut hasher = state.build_hasher();
+        a.hash_unmasked(&mut hasher);
+        assert_eq!(hasher.finish(), state.hash_one(ByValue(token(5))));
+    }
+
+    #[test]
+    fn invariants_hold() {
+        assert!(MangledBox::<()>::new().check_invariants());
+        assert!(MangledBox::<[u64; 7]>::new().check_invariants());
+        assert!(MangledBox::<u16>::from_shares(&[1, 2], &[3, 4]).check_invariants());
+    }
+
+    #[test]
+    fn xor_assign_masked() {
+        let mut a = MangledBox::<[u8; 4]>::new();
+        a.with_unmangled(|p| unsafe { p.write([0x0F, 0xAA, 0x00, 0x12]) });
+        let mut b = MangledBox::<[u8; 4]>::new();
+        b.with_unmangled(|p| unsafe { p.write([0xFF, 0x55, 0x34, 0x12]) });
+        let a_key = unsafe { a.key.assume_init_read() };
+
+        a.xor_assign_masked(&b);
+        assert_eq!(unsafe { a.key.assume_init_read() }, a_key);
+        a.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [0xF0, 0xFF, 0x34, 0x00]));
+        b.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [0xFF, 0x55, 0x34, 0x12]));
+    }
+
+    #[test]
+    fn len_bytes() {
+        assert_eq!(MangledBox::<[u32; 3]>::new().len_bytes(), 12);
+        assert!(!MangledBox::<u8>::new().is_empty());
+        assert!(MangledBox::<()>::new().is_empty());
+    }
+
+    #[test]
+    fn replace() {
+        let mut box_ = MangledBox::<[u16; 3]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3]) });
+        assert_eq!(box_.replace([4, 5, 6]), [1, 2, 3]);
+        assert_eq!(box_.replace([7, 8, 9]), [4, 5, 6]);
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [7, 8, 9]));
+    }
+
+    #[test]
+    fn address_stable_across_moves() {
+        let mut box_ = MangledBox::<[u64; 2]>::new();
+        let before = box_.with_unmangled(|p| p.as_ptr());
+        let mut moved = vec![box_];
+        let mut box_ = moved.pop().unwrap();
+        assert_eq!(box_.with_unmangled(|p| p.as_ptr()), before);
+
+        let old = box_.rotate(MangledBox::new());
+        assert_ne!(box_.with_unmangled(|p| p.as_ptr()), before);
+        drop(old);
+    }
+
+    #[test]
+    #[cfg(feature = "sentinel-wipe")]
+    fn wipe_fills_sentinel() {
+        crate::set_wipe_sentinel(0xA5);
+        let mut box_ = MangledBox::<[u8; 24]>::new();
+        box_.wipe();
+        assert_eq!(unsafe { box_.data.get_mut().assume_init_read() }, [0xA5; 24]);
+        assert_eq!(unsafe { box_.key.assume_init_read() }, [0xA5; 24]);
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [0; 24]));
+    }
+
+    #[test]
+    fn with_unmangled_copy_out() {
+        let mut box_ = MangledBox::<[u32; 4]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 20, 300, 4000]) });
+        assert_eq!(box_.with_unmangled_copy_out(|v| v[2]), 300);
+        assert_eq!(box_.with_unmangled_copy_out(|v| v.iter().sum::<u32>()), 4321);
+    }
+
+    #[test]
+    fn snapshot_masked() {
+        let mut box_ = MangledBox::<u64>::new();
+        box_.with_unmangled(|p| unsafe { p.write(0xDEAD_BEEF) });
+        let snapshot = box_.snapshot_masked();
+        box_.rekey();
+
+        let (data, key) = snapshot.shares();
+        assert_eq!(data.len(), 8);
+        assert_ne!(data, key);
+        snapshot.restore::<u64>().with_unmangled(|p| assert_eq!(unsafe { p.read() }, 0xDEAD_BEEF));
+
+        let res = catch_unwind(AssertUnwindSafe(|| box_.with_unmangled_ref(|_| box_.snapshot_masked())));
+        assert!(res.is_err(), "snapshot taken while unmasked through a shared reference");
+    }
+
+    #[test]
+    fn map_bytes() {
+        let mut box_ = MangledBox::<[u8; 4]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 2, 3, 4]) });
+        box_.map_bytes(|i, b| b.rotate_left(1) ^ i as u8);
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [2, 5, 4, 11]));
+
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            box_.map_bytes(|i, b| if i == 2 { panic!("transformation failed") } else { 0xff - b })
+        }));
+        assert!(res.is_err());
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [253, 250, 4, 11]));
+    }
+
+    #[test]
+    #[cfg(feature = "poison-on-panic")]
+    fn poison_on_panic() {
+        let mut box_ = MangledBox::<[u32; 2]>::new();
+        box_.with_unmangled(|p| unsafe { p.write([1, 1]) });
+        assert_eq!(box_.try_with_unmangled(|p| unsafe { p.read() }), Ok([1, 1]));
+
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            box_.with_unmangled(|p| {
+                unsafe { p.cast::<u32>().write(2) };
+                panic!("interrupted between the two halves");
+            })
+        }));
+        assert!(res.is_err());
+        assert!(box_.is_poisoned());
+        assert_eq!(box_.try_with_unmangled(|_| unreachable!()), Err::<(), _>(Poisoned));
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, [2, 1]));
+
+        box_.clear_poison();
+        assert_eq!(box_.try_with_unmangled(|p| unsafe { p.read() }), Ok([2, 1]));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore = "too slow under miri")]
+    fn one_mebibyte_secret() {
+        type Big = [[u8; 4096]; 256];
+        const LEN: usize = size_of::<Big>();
+
+        // Nothing `Big`-sized may land on the stack, which is smaller than the secret.
+        std::thread::Builder::new()
+            .stack_size(LEN / 2)
+            .spawn(|| {
+                let (draws, bytes) = (keygen::draws(), keygen::drawn_bytes());
+                let mut box_ = MangledBox::<Big>::new();
+                assert_eq!((keygen::draws() - draws, keygen::drawn_bytes() - bytes), (1, LEN), "one full-size key draw");
+
+                let pattern = |i: usize| (i % 251) as u8;
+                box_.with_unmangled(|p| {
+                    let bytes = unsafe { std::slice::from_raw_parts_mut(p.as_ptr().cast::<u8>(), LEN) };
+                    bytes.iter_mut().enumerate().for_each(|(i, b)| *b = pattern(i));
+                });
+                box_.rekey();
+                box_.with_unmangled(|p| {
+                    let bytes = unsafe { std::slice::from_raw_parts(p.as_ptr().cast::<u8>(), LEN) };
+                    assert!(bytes.iter().enumerate().all(|(i, &b)| b == pattern(i)));
+                });
+                drop(box_);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
 }
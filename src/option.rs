@@ -1,16 +1,28 @@
 use std::ptr::{NonNull, null_mut, write};
+#[cfg(feature = "clone-to-uninit")]
+use std::clone::CloneToUninit;
+use std::mem::size_of;
 
-use crate::MangledBoxArbitrary;
+use bytemuck::Pod;
+
+use crate::{MangledBox, MangledBoxArbitrary, SecretContainer};
 
 
 /// [`MangledOption`] is a variant of [`Option`] that is mangled with a random key.
 /// It guarantees that value is initialized whenever [`Some`] variant is used.
 ///
+/// The variant is stored in the niche of the inner box's pointers, so a stray write there
+/// could turn [`None`] into [`Some`]. The [`Some`] payload therefore carries a checksum of
+/// its pointers, verified by every method deciding on the variant (and by the destructor);
+/// a mismatch aborts the process rather than unmangle or drop through bogus pointers.
+///
 /// [`Option`]: std::option::Option
 /// [`Some`]: std::option::Option::Some
 /// [`None`]: std::option::Option::None
+#[derive(Default)]
 pub enum MangledOption<T> {
     Some(MangledBoxArbitrary<T>),
+    #[default]
     None,
 }
 
@@ -32,7 +44,21 @@ impl<T> MangledOption<T> {
 
     /// Returns `true` if the option is a [`Some`] variant.
     pub fn is_some(&self) -> bool {
-        matches!(self, Self::Some(_))
+        matches!(self.checked(), Self::Some(_))
+    }
+
+    /// Returns the option after checking that its variant state is intact.
+    ///
+    /// # Aborts
+    /// Aborts the process if a [`Some`] payload does not match its pointer checksum.
+    fn checked(&self) -> &Self {
+        if let Self::Some(mangled_box) = self
+            && !mangled_box.pointers_intact()
+        {
+            eprintln!("secretmangle: MangledOption variant state is corrupted; aborting");
+            std::process::abort();
+        }
+        self
     }
 
     /// Returns `true` if the option is a [`None`] variant.
@@ -68,9 +94,38 @@ impl<T> MangledOption<T> {
     pub fn insert_by_ptr(&mut self, f: impl FnOnce(NonNull<T>)) {
         let mut new_content_box = MangledBoxArbitrary::new();
         new_content_box.with_unmangled(f);
+        new_content_box.mark_initialized();
         *self = Self::Some(new_content_box);
     }
 
+    /// Constructs the value in place like [`Self::insert_by_ptr`], for an option that must
+    /// be [`None`] at this point.
+    ///
+    /// # Panics
+    /// Panics without calling the constructor if the option is already [`Some`]; this
+    /// catches double initialization of a slot that should have been empty, which
+    /// [`Self::insert_by_ptr`] tolerates by dropping the old value.
+    pub fn init_by_ptr(&mut self, f: impl FnOnce(NonNull<T>)) {
+        assert!(self.is_none(), "MangledOption::init_by_ptr called on an option holding a value");
+        self.insert_by_ptr(f);
+    }
+
+    /// Replaces the value in the option with a clone of `value`, leaving a [`Some`] variant in
+    /// its place. The old value is dropped if it was present, after construction of the new one.
+    ///
+    /// The clone is written straight into the masked allocation, so no owned copy of the whole
+    /// value passes through the stack.
+    #[cfg(feature = "clone-to-uninit")]
+    pub fn insert_cloned(&mut self, value: &T)
+    where
+        T: CloneToUninit,
+    {
+        // # Safety
+        // The pointer handed to the constructor is valid for writes of `size_of::<T>()`
+        // (that is, `size_of_val(value)`) bytes and aligned for `T`.
+        self.insert_by_ptr(|p| unsafe { value.clone_to_uninit(p.as_ptr().cast::<u8>()) });
+    }
+
     /// Unmangles the contents and invokes the provided closure on it. Invokes a default
     /// closure if the option is [`None`] instead.
     ///
@@ -84,6 +139,7 @@ impl<T> MangledOption<T> {
         F: FnOnce(&mut T) -> R,
         G: FnOnce() -> R,
     {
+        self.checked();
         match self {
             MangledOption::Some(mangled_box) => {
                 mangled_box.with_unmangled(|mut ptr| f(unsafe { ptr.as_mut() }))
@@ -106,8 +162,87 @@ impl<T> MangledOption<T> {
         self.map_mut_or_else(|| None, |m| Some(f(m)))
     }
 
+    /// Unmangles the contents and invokes the provided closure on it, returning the masked
+    /// option it produces, or [`None`] if this option is [`None`].
+    ///
+    /// Since the closure hands back an already masked [`MangledOption`], no plaintext of `U`
+    /// leaves it, which allows chaining masked stages (lookup key, derive subkey, ...).
+    pub fn and_then_mut<U, F>(&mut self, f: F) -> MangledOption<U>
+    where
+        F: FnOnce(&mut T) -> MangledOption<U>,
+    {
+        self.map_mut_or_else(MangledOption::new, f)
+    }
+
+    /// Unmangles the contents of both options and invokes the provided closure on them.
+    /// Returns [`None`] without calling the closure unless both options are [`Some`].
+    ///
+    /// The unmangling scopes are nested, so both values are remangled afterwards even if
+    /// the closure panics.
+    pub fn zip_mut<U, F, R>(&mut self, other: &mut MangledOption<U>, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut T, &mut U) -> R,
+    {
+        self.checked();
+        other.checked();
+        match (self, other) {
+            (MangledOption::Some(this), MangledOption::Some(that)) => Some(
+                this.with_unmangled(|mut a| {
+                    that.with_unmangled(|mut b| f(unsafe { a.as_mut() }, unsafe { b.as_mut() }))
+                })
+            ),
+            _ => None,
+        }
+    }
+
+    /// Unmangles the contents and invokes the provided closure on them for observation,
+    /// e.g. logging or assertions, as [`Option::inspect`] does. Does nothing if the option
+    /// is [`None`]. Returns `self` for chaining.
+    ///
+    /// The closure gets a shared reference to signal intent; unmangling still needs `&mut self`.
+    pub fn inspect_mut<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&T),
+    {
+        self.map_mut_or_else(|| (), |m| f(m));
+        self
+    }
+
+    /// Unmangles the contents and runs the predicate on them, clearing the option if
+    /// the predicate returns `false`. Does nothing if the option is [`None`].
+    ///
+    /// The value is dropped only after the predicate returns and the contents are
+    /// remangled, so it is never destroyed while borrowed.
+    pub fn filter_mut<P>(&mut self, pred: P)
+    where
+        P: FnOnce(&T) -> bool,
+    {
+        let keep = self.map_mut_or_else(|| true, |m| pred(m));
+        if !keep {
+            self.clear();
+        }
+    }
+
+    /// Unmangles the contents and runs the predicate on them, taking the value out of the
+    /// option if the predicate returns `true`, as [`Option::take_if`] does. Otherwise, or
+    /// if the option is [`None`], leaves it untouched and returns [`None`].
+    ///
+    /// The value is moved only after the predicate returns and the contents are remangled,
+    /// so the returned option holds the same masked box.
+    pub fn take_if<P>(&mut self, pred: P) -> MangledOption<T>
+    where
+        P: FnOnce(&T) -> bool,
+    {
+        if self.map_mut_or_else(|| false, |m| pred(m)) {
+            self.take()
+        } else {
+            MangledOption::None
+        }
+    }
+
     /// Rekeys the box, preserving its contents.
     pub fn rekey(&mut self) {
+        self.checked();
         match self {
             MangledOption::Some(mangled_box) => {
                 mangled_box.rekey();
@@ -116,8 +251,12 @@ impl<T> MangledOption<T> {
         }
     }
 
-    /// Returns pointer to mangled data.
+    /// Returns pointer to mangled data, or null for [`None`].
+    ///
+    /// Dereferencing it as a `T` yields garbage rather than the value. Prefer
+    /// [`MangledOption::masked_bytes`] for working on the masked bytes directly.
     pub fn as_ptr(&mut self) -> *mut T {
+        self.checked();
         match self {
             MangledOption::Some(mangled_box) => mangled_box.with_mangled(|p| p.as_ptr()),
             MangledOption::None              => null_mut(),
@@ -125,8 +264,72 @@ impl<T> MangledOption<T> {
     }
 }
 
+impl<T: Pod> MangledOption<T> {
+    /// Replaces the contents with a uniformly random value, e.g. to lazily initialize a nonce
+    /// or key slot. The masked data and the key are drawn independently, so the value is never
+    /// present unmasked.
+    ///
+    /// This requires `T: Pod`, so that the random bytes are a valid value.
+    pub fn insert_random(&mut self) {
+        *self = Self::Some(MangledBox::<T>::new_random().into());
+    }
+
+    /// Installs a uniformly random value as [`MangledOption::insert_random`] does if the
+    /// option is [`None`], leaving an existing value alone, and returns the box holding the
+    /// value. This suits lazily generated one-time secrets, e.g. a per-session ephemeral key
+    /// created on first access.
+    ///
+    /// [`None`]: std::option::Option::None
+    pub fn get_or_insert_random(&mut self) -> &mut MangledBoxArbitrary<T> {
+        if self.is_none() {
+            self.insert_random();
+        }
+        match self {
+            MangledOption::Some(mangled_box) => mangled_box,
+            MangledOption::None => unreachable!("a value was just inserted"),
+        }
+    }
+
+    /// Returns a view of the masked bytes for [`Some`], e.g. for flipping bits of the value
+    /// in place by XORing them into the masked form, or [`None`] for [`None`].
+    ///
+    /// This requires `T: Pod`: arbitrary writes to the masked bytes change the value to an
+    /// arbitrary bit pattern, and the value gets dropped later on.
+    ///
+    /// [`Some`]: std::option::Option::Some
+    /// [`None`]: std::option::Option::None
+    pub fn masked_bytes(&mut self) -> Option<&mut [u8]> {
+        self.checked();
+        match self {
+            MangledOption::Some(mangled_box) => {
+                let ptr = mangled_box.with_mangled(|p| p.cast::<u8>());
+                // # Safety
+                // 1. [`ptr`] points to the data allocation of `size_of::<T>()` bytes, which is
+                //    borrowed mutably for the lifetime of the returned slice
+                // 2. Masked bytes are always initialized, as every byte is written by the
+                //    XOR intrinsic on remangling (or zeroed on construction)
+                Some(unsafe { std::slice::from_raw_parts_mut(ptr.as_ptr(), size_of::<T>()) })
+            }
+            MangledOption::None => None,
+        }
+    }
+}
+
+impl<T> SecretContainer for MangledOption<T> {
+    type Value = T;
+
+    fn rekey(&mut self) {
+        MangledOption::rekey(self);
+    }
+
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        MangledOption::map_mut(self, f)
+    }
+}
+
 impl<T> Drop for MangledOption<T> {
     fn drop(&mut self) {
+        self.checked();
         match self {
             MangledOption::Some(mangled_box) => {
                 unsafe { mangled_box.drop_in_place(); }
@@ -137,18 +340,11 @@ impl<T> Drop for MangledOption<T> {
     }
 }
 
-impl<T> Default for MangledOption<T> {
-    fn default() -> Self {
-        Self::None
-    }
-}
-
 
 #[cfg(all(test, not(miri)))]
 mod tests {
     use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::mem::size_of;
-
+    use std::panic::{catch_unwind, AssertUnwindSafe};
     use super::*;
 
 
@@ -167,6 +363,88 @@ mod tests {
         assert_eq!(option.map_mut_or_else(|| 5, |x| { *x += 1; *x }), 5);
     }
     
+    #[test]
+    fn test_filter_mut() {
+        let mut option = MangledOption::filled_with_unmasked_value(42);
+        option.filter_mut(|x| *x == 42);
+        assert_eq!(option.map_mut(|x| *x), Some(42));
+
+        option.filter_mut(|x| *x != 42);
+        assert!(option.is_none());
+
+        option.filter_mut(|_| unreachable!("predicate called on None"));
+        assert!(option.is_none());
+    }
+
+    #[test]
+    fn test_filter_mut_drops_once() {
+        static DROP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        struct DropCounter;
+        impl Drop for DropCounter {
+            fn drop(&mut self) {
+                DROP_COUNT.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut option = MangledOption::filled_with_unmasked_value(DropCounter);
+        option.filter_mut(|_| true);
+        assert_eq!(DROP_COUNT.load(Ordering::SeqCst), 0);
+
+        option.filter_mut(|_| false);
+        assert_eq!(DROP_COUNT.load(Ordering::SeqCst), 1);
+        drop(option);
+        assert_eq!(DROP_COUNT.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_and_then_mut() {
+        let mut master = MangledOption::filled_with_unmasked_value(0x1234u32);
+        let mut subkey = master.and_then_mut(|m| {
+            let mut derived = MangledOption::new();
+            derived.insert_by_ptr(|p: NonNull<u64>| unsafe { p.write(u64::from(*m) << 8) });
+            derived
+        });
+        assert_eq!(subkey.map_mut(|x| *x), Some(0x123400));
+
+        let mut empty = MangledOption::<u32>::new();
+        let mut chained = empty.and_then_mut(|_| -> MangledOption<u64> { unreachable!() });
+        assert!(chained.is_none());
+        assert_eq!(chained.map_mut(|x| *x), None);
+    }
+
+    #[test]
+    fn test_zip_mut() {
+        let mut nonce = MangledOption::filled_with_unmasked_value(7u64);
+        let mut key = MangledOption::filled_with_unmasked_value(String::from("key"));
+        let zipped = nonce.zip_mut(&mut key, |n, k| {
+            *n += 1;
+            k.push_str("_7");
+            k.len()
+        });
+        assert_eq!(zipped, Some(5));
+        assert_eq!(nonce.map_mut(|n| *n), Some(8));
+        assert_eq!(key.map_mut(|k| k.clone()), Some(String::from("key_7")));
+
+        let mut empty = MangledOption::<u8>::new();
+        assert_eq!(nonce.zip_mut(&mut empty, |_, _| unreachable!()), None::<()>);
+        assert_eq!(empty.zip_mut(&mut nonce, |_, _| unreachable!()), None::<()>);
+    }
+
+    #[test]
+    fn test_zip_mut_panic_remangles_both() {
+        let mut a = MangledOption::filled_with_unmasked_value(1u32);
+        let mut b = MangledOption::filled_with_unmasked_value(2u32);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            a.zip_mut(&mut b, |_, _| panic!("closure failed"))
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(a.map_mut(|x| *x), Some(1));
+        assert_eq!(b.map_mut(|x| *x), Some(2));
+    }
+
     #[test]
     fn test_new_is_none() {
         let option: MangledOption<i32> = MangledOption::new();
@@ -206,6 +484,18 @@ mod tests {
         assert_eq!(option.map_mut(|x| *x), Some(50));
     }
 
+    #[test]
+    #[cfg(feature = "clone-to-uninit")]
+    fn test_insert_cloned() {
+        let secret = String::from("borrowed secret");
+        let mut option = MangledOption::new();
+        option.insert_cloned(&secret);
+        assert_eq!(option.map_mut(|x| x.clone()), Some(secret));
+
+        option.insert_cloned(&String::from("replacement"));
+        assert_eq!(option.map_mut(|x| x.len()), Some(11));
+    }
+
     #[test]
     fn test_insert_by_ptr() {
         let mut option = MangledOption::<usize>::new();
@@ -410,5 +700,134 @@ mod tests {
         });
         assert!(had.is_some());
     }
+
+    #[test]
+    fn test_take_if() {
+        let mut option = MangledOption::filled_with_unmasked_value(String::from("secret"));
+        assert!(option.take_if(|s| s.is_empty()).is_none());
+        assert!(option.is_some());
+
+        let mut taken = option.take_if(|s| s.starts_with("sec"));
+        assert!(option.is_none());
+        assert_eq!(taken.map_mut(|s| s.clone()).as_deref(), Some("secret"));
+        assert!(option.take_if(|_| true).is_none());
+    }
+
+    #[test]
+    fn test_insert_random() {
+        let mut first = MangledOption::<[u64; 4]>::new();
+        first.insert_random();
+        let mut second = MangledOption::filled_with_unmasked_value([0u64; 4]);
+        second.insert_random();
+
+        let a = first.map_mut(|v| *v).unwrap();
+        let b = second.map_mut(|v| *v).unwrap();
+        assert_ne!(a, [0; 4]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_masked_bytes_flip() {
+        let mut option = MangledOption::<u32>::new();
+        assert!(option.masked_bytes().is_none());
+
+        option.insert_unmasked_value(0x0F0F0F0F);
+        let masked = option.masked_bytes().unwrap();
+        assert_eq!(masked.len(), 4);
+        for byte in masked.iter_mut() {
+            *byte ^= 0xFF;
+        }
+        assert_eq!(option.map_mut(|v| *v), Some(0xF0F0F0F0));
+    }
+
+    #[test]
+    fn test_none_uses_box_niche() {
+        fn check<T>() {
+            assert_eq!(size_of::<MangledOption<T>>(), size_of::<MangledBoxArbitrary<T>>());
+        }
+        check::<()>();
+        check::<u8>();
+        check::<String>();
+        check::<[u64; 32]>();
+    }
+
+    #[test]
+    fn test_some_payload_checksum() {
+        let mut option = MangledOption::filled_with_unmasked_value(7u64);
+        assert!(option.is_some());
+        assert!(matches!(&option, MangledOption::Some(b) if b.pointers_intact()));
+
+        let taken = option.take();
+        assert!(option.is_none());
+        assert!(matches!(&taken, MangledOption::Some(b) if b.pointers_intact()), "moving broke the checksum");
+
+        option.insert_random();
+        assert!(matches!(&option, MangledOption::Some(b) if b.pointers_intact()));
+    }
+
+    #[test]
+    fn test_drop_leaves_valid_none() {
+        let secret = MangledOption::filled_with_unmasked_value(String::from("secret"));
+        let mut option = std::mem::ManuallyDrop::new(secret);
+        // # Safety
+        // Only the discriminant is inspected afterwards, which `Drop` has overwritten with `None`.
+        unsafe { std::mem::ManuallyDrop::drop(&mut option) };
+        assert!(matches!(*option, MangledOption::None));
+        assert!(option.is_none());
+    }
+
+    #[test]
+    fn test_get_or_insert_random() {
+        let mut option = MangledOption::<[u64; 2]>::new();
+        let first = option.get_or_insert_random().with_unmangled(|p| unsafe { p.read() });
+        let again = option.get_or_insert_random().with_unmangled(|p| unsafe { p.read() });
+        assert_eq!(first, again);
+        assert_ne!(first, [0; 2]);
+
+        option.insert_unmasked_value([1, 2]);
+        option.get_or_insert_random();
+        assert_eq!(option.map_mut(|v| *v), Some([1, 2]));
+    }
+
+    #[test]
+    fn test_init_by_ptr() {
+        let mut option = MangledOption::<u32>::new();
+        option.init_by_ptr(|p| unsafe { p.write(11) });
+        assert_eq!(option.map_mut(|v| *v), Some(11));
+
+        let res = catch_unwind(AssertUnwindSafe(|| option.init_by_ptr(|_| unreachable!())));
+        assert!(res.is_err());
+        assert_eq!(option.map_mut(|v| *v), Some(11));
+    }
+
+    #[test]
+    fn test_inspect_mut() {
+        let mut seen = None;
+        let mut option = MangledOption::filled_with_unmasked_value(42);
+        let doubled = option.inspect_mut(|x| seen = Some(*x)).map_mut(|x| *x * 2);
+        assert_eq!((seen, doubled), (Some(42), Some(84)));
+
+        let mut empty = MangledOption::<u32>::new();
+        assert!(empty.inspect_mut(|_| unreachable!("closure called on None")).is_none());
+    }
+
+    #[test]
+    fn test_one_mebibyte_rekey() {
+        const LEN: usize = 1 << 20;
+
+        // Nothing `LEN`-sized may land on the stack, which is smaller than the secret.
+        std::thread::Builder::new()
+            .stack_size(LEN / 2)
+            .spawn(|| {
+                let mut option = MangledOption::<[u8; LEN]>::new();
+                option.insert_by_ptr(|p| unsafe { p.cast::<u8>().write_bytes(0x5a, LEN) });
+                option.rekey();
+                let intact = option.map_mut(|bytes| bytes.iter().all(|&b| b == 0x5a));
+                assert_eq!(intact, Some(true));
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
 }
 
@@ -0,0 +1,123 @@
+//! Pool recycling the allocations of [`MangledBox`]es for high-churn workloads.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, PoisonError};
+
+use bytemuck::NoUninit;
+
+use crate::MangledBox;
+
+/// Pool of [`MangledBox`] allocations, handing them out via [`MangledPool::acquire`].
+///
+/// A box is wiped (both data and key zeroed) as soon as it is returned, so no old secret
+/// lingers in the pool, and gets a fresh key on every acquisition. Only the heap
+/// allocations are reused; the entropy draw per acquisition stays.
+pub struct MangledPool<T: NoUninit> {
+    idle: Mutex<Vec<MangledBox<T>>>,
+}
+
+impl<T: NoUninit> MangledPool<T> {
+    /// Creates an empty pool.
+    pub fn new() -> Self {
+        Self { idle: Mutex::new(Vec::new()) }
+    }
+
+    /// Returns a box as if freshly constructed by [`MangledBox::new`], reusing an idle
+    /// allocation if there is one.
+    pub fn acquire(&self) -> PooledMangledBox<'_, T> {
+        let idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner).pop();
+        let box_ = match idle {
+            Some(mut box_) => {
+                box_.reinit();
+                box_
+            }
+            None => MangledBox::new(),
+        };
+        PooledMangledBox { box_: Some(box_), pool: self }
+    }
+
+    /// Returns the number of idle allocations kept by the pool.
+    pub fn idle_len(&self) -> usize {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+impl<T: NoUninit> Default for MangledPool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`MangledBox`] borrowed from a [`MangledPool`], wiped and returned to it when dropped.
+pub struct PooledMangledBox<'a, T: NoUninit> {
+    /// Always [`Some`] until dropped.
+    box_: Option<MangledBox<T>>,
+    pool: &'a MangledPool<T>,
+}
+
+impl<T: NoUninit> Deref for PooledMangledBox<'_, T> {
+    type Target = MangledBox<T>;
+
+    fn deref(&self) -> &MangledBox<T> {
+        self.box_.as_ref().unwrap()
+    }
+}
+
+impl<T: NoUninit> DerefMut for PooledMangledBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut MangledBox<T> {
+        self.box_.as_mut().unwrap()
+    }
+}
+
+impl<T: NoUninit> Drop for PooledMangledBox<'_, T> {
+    fn drop(&mut self) {
+        if let Some(mut box_) = self.box_.take() {
+            box_.wipe();
+            self.pool.idle.lock().unwrap_or_else(PoisonError::into_inner).push(box_);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_wiped_allocations() {
+        let pool = MangledPool::<[u8; 32]>::new();
+
+        let mut first = pool.acquire();
+        first.with_unmangled(|p| unsafe { p.write([7; 32]) });
+        let first_addr = first.with_unmangled(|p| p.addr());
+        drop(first);
+        assert_eq!(pool.idle_len(), 1);
+
+        let mut second = pool.acquire();
+        assert_eq!(pool.idle_len(), 0);
+        let (second_addr, value) = second.with_unmangled(|p| (p.addr(), unsafe { p.read() }));
+        assert_eq!(second_addr, first_addr, "allocation not reused");
+        assert_ne!(value, [7; 32], "old secret survived recycling");
+
+        let _third = pool.acquire();
+        drop(second);
+        assert_eq!(pool.idle_len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "poison-on-panic")]
+    fn recycled_box_is_not_poisoned() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let pool = MangledPool::<[u8; 32]>::new();
+
+        let mut first = pool.acquire();
+        let res = catch_unwind(AssertUnwindSafe(|| first.with_unmangled(|_| panic!("boom"))));
+        assert!(res.is_err());
+        assert!(first.is_poisoned());
+        drop(first);
+
+        let mut second = pool.acquire();
+        assert!(!second.is_poisoned(), "poison survived recycling");
+        assert!(second.try_with_unmangled(|_| ()).is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+//! Automatic rekeying of a [`SecretContainer`] after a configurable number of accesses.
+
+use crate::SecretContainer;
+
+/// How often an [`AutoRekey`] wrapper draws a fresh key for its container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RekeyPolicy {
+    /// Keep the key until rekeyed explicitly.
+    #[default]
+    Never,
+    /// Rekey after every access.
+    EveryAccess,
+    /// Rekey after every `n`-th access; `EveryN(0)` and `EveryN(1)` behave like
+    /// [`RekeyPolicy::EveryAccess`].
+    EveryN(u32),
+}
+
+/// Wrapper applying a [`RekeyPolicy`] to a container, bounding how long a single key
+/// stays paired with the secret without paying for fresh entropy on every access.
+///
+/// Accesses are counted in [`SecretContainer::map_mut`], including ones whose closure
+/// panics; the rekey happens after the contents are remangled. The policy and the
+/// counter are non-secret metadata.
+pub struct AutoRekey<C: SecretContainer> {
+    inner: C,
+    policy: RekeyPolicy,
+    /// Accesses since the last rekey; only advanced under [`RekeyPolicy::EveryN`].
+    accesses: u32,
+}
+
+impl<C: SecretContainer> AutoRekey<C> {
+    /// Wraps `inner`, rekeying it as prescribed by `policy`.
+    pub fn new(inner: C, policy: RekeyPolicy) -> Self {
+        Self { inner, policy, accesses: 0 }
+    }
+
+    /// Returns the current policy.
+    pub fn policy(&self) -> RekeyPolicy {
+        self.policy
+    }
+
+    /// Replaces the policy, restarting the access count.
+    pub fn set_policy(&mut self, policy: RekeyPolicy) {
+        self.policy = policy;
+        self.accesses = 0;
+    }
+
+    /// Unwraps the container.
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+
+    /// Counts an access and reports whether it should be followed by a rekey.
+    fn tick(&mut self) -> bool {
+        match self.policy {
+            RekeyPolicy::Never => false,
+            RekeyPolicy::EveryAccess => true,
+            RekeyPolicy::EveryN(n) => {
+                self.accesses += 1;
+                if self.accesses >= n {
+                    self.accesses = 0;
+                    true
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Rekeys the borrowed container when dropped, so that an access whose closure panics
+/// still counts towards the policy.
+struct RekeyOnDrop<'a, C: SecretContainer> {
+    inner: &'a mut C,
+    armed: bool,
+}
+
+impl<C: SecretContainer> Drop for RekeyOnDrop<'_, C> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.inner.rekey();
+        }
+    }
+}
+
+impl<C: SecretContainer> SecretContainer for AutoRekey<C> {
+    type Value = C::Value;
+
+    fn rekey(&mut self) {
+        self.inner.rekey();
+        self.accesses = 0;
+    }
+
+    fn map_mut<R>(&mut self, f: impl FnOnce(&mut C::Value) -> R) -> Option<R> {
+        let armed = self.tick();
+        let guard = RekeyOnDrop { inner: &mut self.inner, armed };
+        guard.inner.map_mut(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use super::*;
+
+    /// Plain container counting the rekeys it receives.
+    #[derive(Default)]
+    struct Counting {
+        value: u64,
+        rekeys: usize,
+    }
+
+    impl SecretContainer for Counting {
+        type Value = u64;
+
+        fn rekey(&mut self) {
+            self.rekeys += 1;
+        }
+
+        fn map_mut<R>(&mut self, f: impl FnOnce(&mut u64) -> R) -> Option<R> {
+            Some(f(&mut self.value))
+        }
+    }
+
+    fn rekeys_after(policy: RekeyPolicy, accesses: usize) -> usize {
+        let mut wrapped = AutoRekey::new(Counting::default(), policy);
+        for _ in 0..accesses {
+            wrapped.map_mut(|v| *v += 1);
+        }
+        let inner = wrapped.into_inner();
+        assert_eq!(inner.value, accesses as u64);
+        inner.rekeys
+    }
+
+    #[test]
+    fn policies() {
+        assert_eq!(rekeys_after(RekeyPolicy::Never, 10), 0);
+        assert_eq!(rekeys_after(RekeyPolicy::EveryAccess, 10), 10);
+        assert_eq!(rekeys_after(RekeyPolicy::EveryN(0), 10), 10);
+        assert_eq!(rekeys_after(RekeyPolicy::EveryN(1), 10), 10);
+        assert_eq!(rekeys_after(RekeyPolicy::EveryN(3), 10), 3);
+        assert_eq!(rekeys_after(RekeyPolicy::EveryN(3), 2), 0);
+    }
+
+    #[test]
+    fn panicking_access_counts() {
+        let mut wrapped = AutoRekey::new(Counting::default(), RekeyPolicy::EveryN(2));
+        wrapped.map_mut(|_| {});
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            wrapped.map_mut(|_| panic!("closure failed"));
+        }));
+        assert!(result.is_err());
+        assert_eq!(wrapped.into_inner().rekeys, 1);
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn wraps_real_box() {
+        let mut wrapped = AutoRekey::new(crate::MangledBox::<u64>::new(), RekeyPolicy::EveryN(2));
+        for i in 0..5 {
+            wrapped.map_mut(|v| *v = i);
+            assert_eq!(wrapped.map_mut(|v| *v), Some(i));
+        }
+    }
+}
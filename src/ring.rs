@@ -0,0 +1,101 @@
+//! Fixed-size window of the most recent masked secrets.
+
+use std::ptr::NonNull;
+
+use bytemuck::NoUninit;
+
+use crate::MangledBox;
+
+/// Ring buffer of the `K` most recent masked secrets, e.g. derived session keys kept for
+/// out-of-order packets.
+///
+/// [`MangledRingBuffer::push`] generates each secret masked from the start, and evicts the
+/// oldest one once the buffer is full; the evicted box is dropped right away, zeroing both
+/// its data and its key, so the rolled-out secret is destroyed.
+pub struct MangledRingBuffer<T: NoUninit, const K: usize> {
+    slots: [Option<MangledBox<T>>; K],
+
+    /// Slot the next secret goes to, holding the oldest one if the buffer is full.
+    next: usize,
+}
+
+impl<T: NoUninit, const K: usize> MangledRingBuffer<T, K> {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        const { assert!(K > 0, "a ring buffer needs at least one slot") };
+        Self { slots: std::array::from_fn(|_| None), next: 0 }
+    }
+
+    /// Returns the number of secrets held, at most `K`.
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+
+    /// Returns whether no secret has been pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.slots[0].is_none()
+    }
+
+    /// Pushes a new uniformly random secret, as generated by [`MangledBox::new_random`],
+    /// evicting and zeroing the oldest one if the buffer is full.
+    pub fn push(&mut self) {
+        let evicted = self.slots[self.next].replace(MangledBox::new_random());
+        drop(evicted);
+        self.next = (self.next + 1) % K;
+    }
+
+    /// Unmangles the `i`-th most recent secret, `0` being the newest, and invokes the
+    /// provided closure on it. Whether the closure panics or returns normally, the secret
+    /// is remangled.
+    ///
+    /// Returns [`None`] without calling the closure if fewer than `i + 1` secrets are held.
+    pub fn with_slot<F, R>(&mut self, i: usize, f: F) -> Option<R>
+    where
+        F: FnOnce(NonNull<T>) -> R,
+    {
+        if i >= K {
+            return None;
+        }
+        let index = (self.next + K - 1 - i) % K;
+        self.slots[index].as_mut().map(|box_| box_.with_unmangled(f))
+    }
+}
+
+impl<T: NoUninit, const K: usize> Default for MangledRingBuffer<T, K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_most_recent() {
+        let mut ring = MangledRingBuffer::<u64, 3>::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.with_slot(0, |_| ()), None);
+
+        let mut pushed = Vec::new();
+        for _ in 0..5 {
+            ring.push();
+            pushed.push(ring.with_slot(0, |p| unsafe { p.read() }).unwrap());
+        }
+        assert_eq!(ring.len(), 3);
+        for i in 0..3 {
+            assert_eq!(ring.with_slot(i, |p| unsafe { p.read() }), Some(pushed[4 - i]));
+        }
+        assert_eq!(ring.with_slot(3, |_| ()), None);
+    }
+
+    #[test]
+    fn partially_filled() {
+        let mut ring = MangledRingBuffer::<[u8; 16], 4>::new();
+        ring.push();
+        ring.push();
+        assert_eq!(ring.len(), 2);
+        assert!(ring.with_slot(1, |_| ()).is_some());
+        assert!(ring.with_slot(2, |_| ()).is_none());
+    }
+}
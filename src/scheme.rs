@@ -0,0 +1,139 @@
+//! Masking algebras: how a [`MangledBox`](crate::MangledBox) combines its key with the
+//! plaintext.
+
+use std::mem::size_of;
+use std::sync::atomic::{fence, Ordering};
+
+use crate::nouninit::xor_chunks;
+
+/// Masking algebra of a [`MangledBox`](crate::MangledBox), selected by its third type
+/// parameter, e.g. `MangledBox::<u64, Global, AddMod256>::new_in(Global)`.
+///
+/// Rekeying combines the same random differential into both the data and the key with
+/// [`MaskScheme::mask`], which preserves the value under either algebra.
+///
+/// Neither scheme authenticates the masked data: whoever can write it changes the value
+/// predictably, bit by bit under [`Xor`] and by modular addition under [`AddMod256`].
+/// Operations relying on the XOR algebra itself, such as the shares of
+/// [`MangledBox::into_shares`](crate::MangledBox::into_shares) or comparisons in the
+/// masked domain, are only available with [`Xor`].
+///
+/// Only [`MangledBox`](crate::MangledBox) is parameterized by the scheme. The other
+/// containers, such as [`MangledBoxArbitrary`](crate::MangledBoxArbitrary) (whose masking
+/// is written in assembly), [`MangledOption`](crate::MangledOption) and
+/// [`DoubleMangledBox`](crate::DoubleMangledBox), always mask by XOR.
+///
+/// # Safety
+/// [`MaskScheme::unmask`] must exactly invert [`MaskScheme::mask`] under the same key, and
+/// both must act on each byte independently and leave every byte initialized.
+pub unsafe trait MaskScheme {
+    /// Masks the `size_of::<T>()` bytes at `data` in place with the key at `key`. The
+    /// masking is guaranteed to not be reordered after any later operation.
+    ///
+    /// # Safety
+    /// - `data` and `key` must be correctly aligned for `T`
+    /// - `data` must point to at least `size_of::<T>()` initialized bytes valid for reads
+    ///   and writes, and `key` to as many initialized bytes valid for reads
+    /// - `data` and `key` must not overlap
+    unsafe fn mask<T>(data: *mut u8, key: *const u8);
+
+    /// Reverts [`MaskScheme::mask`] with the same key, with the same ordering guarantee.
+    ///
+    /// # Safety
+    /// Same as [`MaskScheme::mask`].
+    unsafe fn unmask<T>(data: *mut u8, key: *const u8);
+}
+
+/// Masking by XOR, `data = plaintext ^ key`; the default scheme.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xor;
+
+// # Safety
+// XOR is its own inverse and acts bytewise.
+unsafe impl MaskScheme for Xor {
+    unsafe fn mask<T>(data: *mut u8, key: *const u8) {
+        unsafe { xor_chunks::<T>(data, key) }
+    }
+
+    unsafe fn unmask<T>(data: *mut u8, key: *const u8) {
+        unsafe { xor_chunks::<T>(data, key) }
+    }
+}
+
+/// Masking by bytewise addition, `data = plaintext + key (mod 256)` in every byte.
+///
+/// Flipping a bit of the masked data adds or subtracts a power of two to the plaintext
+/// byte, depending on the unknown masked bit, rather than flipping the same plaintext bit.
+/// Slower than [`Xor`], as it works a byte at a time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AddMod256;
+
+// # Safety
+// Wrapping subtraction inverts wrapping addition of the same byte.
+unsafe impl MaskScheme for AddMod256 {
+    unsafe fn mask<T>(data: *mut u8, key: *const u8) {
+        unsafe { combine_bytes::<T>(data, key, u8::wrapping_add) }
+    }
+
+    unsafe fn unmask<T>(data: *mut u8, key: *const u8) {
+        unsafe { combine_bytes::<T>(data, key, u8::wrapping_sub) }
+    }
+}
+
+/// Replaces every data byte `d` with `op(d, k)` for the corresponding key byte `k`,
+/// writing each with a volatile store, then fences.
+///
+/// # Safety
+/// Same as [`MaskScheme::mask`].
+unsafe fn combine_bytes<T>(data: *mut u8, key: *const u8, op: fn(u8, u8) -> u8) {
+    if size_of::<T>() == 0 {
+        // No bytes to mask, so no access to order either.
+        return;
+    }
+    for i in 0..size_of::<T>() {
+        unsafe {
+            let combined = op(data.add(i).read(), key.add(i).read());
+            data.add(i).write_volatile(combined);
+        }
+    }
+    fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::Global;
+
+    use super::*;
+    use crate::MangledBox;
+
+    #[test]
+    fn add_mod_256_roundtrip() {
+        let value: [u8; 48] = std::array::from_fn(|i| (i as u8).wrapping_mul(7));
+        let mut box_ = MangledBox::<[u8; 48], Global, AddMod256>::new_in(Global);
+        box_.with_unmangled(|p| unsafe { p.write(value) });
+        box_.rekey();
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, value));
+
+        box_.map_bytes(|i, b| b.wrapping_sub(i as u8));
+        let mut other = MangledBox::<[u8; 48]>::new();
+        other.with_unmangled(|p| unsafe { p.write([1; 48]) });
+        let mixed = crate::with_two(&mut box_, &mut other, |a, b| std::array::from_fn(|i| a[i] ^ b[i]));
+        let expected: [u8; 48] = std::array::from_fn(|i| value[i].wrapping_sub(i as u8) ^ 1);
+        assert_eq!(mixed, expected);
+
+        let mut boxes = [box_];
+        crate::rekey_all(&mut boxes);
+        let [mut box_] = boxes;
+        box_.with_unmangled(|p| assert_eq!(unsafe { p.read() }, std::array::from_fn(|i| value[i].wrapping_sub(i as u8))));
+    }
+
+    #[test]
+    fn add_mod_256_masks_additively() {
+        let mut data = [200u8, 0, 255];
+        let key = [100u8, 1, 1];
+        unsafe { AddMod256::mask::<[u8; 3]>(data.as_mut_ptr(), key.as_ptr()) };
+        assert_eq!(data, [44, 1, 0]);
+        unsafe { AddMod256::unmask::<[u8; 3]>(data.as_mut_ptr(), key.as_ptr()) };
+        assert_eq!(data, [200, 0, 255]);
+    }
+}
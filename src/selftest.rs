@@ -0,0 +1,144 @@
+//! Power-on self-test of the masking primitives, for deployments which want to
+//! refuse handling secrets if e.g. the assembly path is miscompiled on their CPU.
+
+use std::error::Error;
+use std::fmt;
+use std::mem::{align_of, size_of};
+
+use crate::arbitrary::xor_intrinsic::xor_chunks_intrinsic_baseline;
+use crate::{MangledBox, MangledBoxArbitrary};
+
+/// Failure reported by [`self_test`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SelfTestError {
+    /// The XOR primitive produced wrong bytes for a `size`-byte type placed at
+    /// `offset` bytes into a cache-line-aligned buffer.
+    Xor { size: usize, offset: usize },
+    /// A mangled box did not give back the value stored in it.
+    BoxRoundTrip,
+}
+
+impl fmt::Display for SelfTestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelfTestError::Xor { size, offset } => {
+                write!(f, "XOR primitive failed for {size} bytes at offset {offset}")
+            }
+            SelfTestError::BoxRoundTrip => write!(f, "mangled box failed to round-trip a value"),
+        }
+    }
+}
+
+impl Error for SelfTestError {}
+
+/// Checks that the masking primitives behave correctly on this machine.
+///
+/// Buffers of assorted sizes and alignments are filled with known patterns and
+/// XORed forward and backward; the original must be restored, `data ^ data` must
+/// be zero, and bytes outside the target range must stay untouched. Then both
+/// box types must round-trip a test value.
+///
+/// Meant to be called once at startup, before any real secret is handled.
+pub fn self_test() -> Result<(), SelfTestError> {
+    check_xor::<u8>()?;
+    check_xor::<u16>()?;
+    check_xor::<u32>()?;
+    check_xor::<u64>()?;
+    check_xor::<u128>()?;
+    check_xor::<[u8; 3]>()?;
+    check_xor::<[u8; 33]>()?;
+    check_xor::<[u16; 7]>()?;
+    check_xor::<[u32; 16]>()?;
+    check_xor::<[u64; 9]>()?;
+    check_xor::<[u8; 255]>()?;
+    check_boxes()
+}
+
+const SCRATCH_LEN: usize = 512;
+
+#[repr(C, align(64))]
+struct Scratch([u8; SCRATCH_LEN]);
+
+impl Scratch {
+    fn patterned(mul: u8, add: u8) -> Self {
+        Self(std::array::from_fn(|i| (i as u8).wrapping_mul(mul).wrapping_add(add)))
+    }
+}
+
+/// Runs the XOR primitive on `T` at a few offsets which keep `T` aligned.
+fn check_xor<T>() -> Result<(), SelfTestError> {
+    let size = size_of::<T>();
+    for offset in [0, align_of::<T>(), 3 * align_of::<T>()] {
+        let error = SelfTestError::Xor { size, offset };
+        let in_range = |i: usize| (offset..offset + size).contains(&i);
+
+        let mut data = Scratch::patterned(31, 7);
+        let key = Scratch::patterned(17, 0xA5);
+        let original = Scratch::patterned(31, 7);
+
+        let data_ptr = unsafe { data.0.as_mut_ptr().add(offset) };
+        let key_ptr = unsafe { key.0.as_ptr().add(offset) };
+
+        // # Safety
+        // 1. Both buffers are 64-aligned and offsets are multiples of `align_of::<T>()`
+        // 2. `offset + size_of::<T>()` fits into the buffers for all types tested
+        // 3. [`data`] and [`key`] are distinct buffers
+        unsafe { xor_chunks_intrinsic_baseline::<T>(data_ptr, key_ptr) };
+        let forward_ok = (0..SCRATCH_LEN).all(|i| {
+            let expected = if in_range(i) { original.0[i] ^ key.0[i] } else { original.0[i] };
+            data.0[i] == expected
+        });
+        if !forward_ok {
+            return Err(error);
+        }
+
+        unsafe { xor_chunks_intrinsic_baseline::<T>(data_ptr, key_ptr) };
+        if data.0 != original.0 {
+            return Err(error);
+        }
+
+        // # Safety
+        // As above, passing the same pointer twice.
+        unsafe { xor_chunks_intrinsic_baseline::<T>(data_ptr, data_ptr) };
+        let zeroed_ok = (0..SCRATCH_LEN).all(|i| {
+            let expected = if in_range(i) { 0 } else { original.0[i] };
+            data.0[i] == expected
+        });
+        if !zeroed_ok {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+/// Stores a test value into both box types, rekeys and reads it back.
+fn check_boxes() -> Result<(), SelfTestError> {
+    const VALUE: [u64; 4] = [0x0123456789ABCDEF, 0xFEDCBA9876543210, 0, u64::MAX];
+
+    let mut nouninit = MangledBox::<[u64; 4]>::new();
+    nouninit.with_unmangled(|p| unsafe { p.write(VALUE) });
+    nouninit.rekey();
+    let nouninit_ok = nouninit.with_unmangled(|p| unsafe { p.read() } == VALUE);
+
+    let mut arbitrary = MangledBoxArbitrary::<[u64; 4]>::new();
+    arbitrary.with_unmangled(|p| unsafe { p.write(VALUE) });
+    arbitrary.rekey();
+    let arbitrary_ok = arbitrary.with_unmangled(|p| unsafe { p.read() } == VALUE);
+
+    if nouninit_ok && arbitrary_ok {
+        Ok(())
+    } else {
+        Err(SelfTestError::BoxRoundTrip)
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_on_this_machine() {
+        assert_eq!(self_test(), Ok(()));
+    }
+}
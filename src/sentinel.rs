@@ -0,0 +1,31 @@
+//! Recognizable fill pattern for wiped secrets, for memory forensics.
+
+use std::sync::atomic::{AtomicU8, Ordering, fence};
+
+static SENTINEL: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the byte that dropped boxes fill their data and key allocations with, process-wide.
+///
+/// A non-zero sentinel lets memory forensics tell deliberately wiped secrets apart from
+/// incidental zero bytes. The default is `0x00`.
+pub fn set_wipe_sentinel(byte: u8) {
+    SENTINEL.store(byte, Ordering::Relaxed);
+}
+
+/// Returns the byte set by [`set_wipe_sentinel`].
+pub fn wipe_sentinel() -> u8 {
+    SENTINEL.load(Ordering::Relaxed)
+}
+
+/// Overwrites `len` bytes at `ptr` with the sentinel, with volatile stores followed by
+/// a fence, so that the wipe is neither elided nor reordered after later operations.
+///
+/// # Safety
+/// `ptr` must be valid for writes of `len` bytes.
+pub(crate) unsafe fn fill(ptr: *mut u8, len: usize) {
+    let byte = wipe_sentinel();
+    for i in 0..len {
+        unsafe { ptr.add(i).write_volatile(byte) };
+    }
+    fence(Ordering::SeqCst);
+}
@@ -0,0 +1,91 @@
+//! Process-wide hook observing accesses to masked containers, for auditing access patterns.
+//!
+//! Only non-secret metadata reaches the observer: the kind of operation and the size of
+//! the masked value. No pointers or contents are ever passed.
+
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// Kind of operation performed on a masked container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AccessKind {
+    /// The contents (or a part of them) were unmangled, including to be overwritten.
+    Unmangle,
+    /// The container was rekeyed.
+    Rekey,
+    /// The container was dropped.
+    Drop,
+}
+
+/// Metadata describing a single access, passed to the observer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct AccessEvent {
+    pub kind: AccessKind,
+    /// `size_of::<T>()` of the container's value type.
+    pub size: usize,
+}
+
+type Observer = Arc<dyn Fn(AccessEvent) + Send + Sync>;
+
+static OBSERVER: RwLock<Option<Observer>> = RwLock::new(None);
+
+/// Installs `observer` to be called on every access to any masked container,
+/// replacing the previous one.
+///
+/// The observer runs on the accessing thread, before the operation for unmangling and
+/// rekeying. It may itself access masked containers.
+pub fn set_access_observer(observer: impl Fn(AccessEvent) + Send + Sync + 'static) {
+    *OBSERVER.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(observer));
+}
+
+/// Removes the observer, if any.
+pub fn clear_access_observer() {
+    *OBSERVER.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Reports an access to the observer, if one is installed.
+pub(crate) fn record(kind: AccessKind, size: usize) {
+    // The lock is released before calling, so that the observer may access containers
+    // or replace itself.
+    let observer = OBSERVER.read().unwrap_or_else(PoisonError::into_inner).clone();
+    if let Some(observer) = observer {
+        observer(AccessEvent { kind, size });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::MangledBox;
+
+    #[test]
+    fn observes_accesses() {
+        // Other tests run concurrently, so only events for this odd size are collected.
+        #[derive(bytemuck::NoUninit, Clone, Copy)]
+        #[repr(C)]
+        struct Odd([u8; 256], [u8; 256], [u8; 256], [u8; 9]);
+        const SIZE: usize = size_of::<Odd>();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+        set_access_observer(move |event| {
+            if event.size == SIZE {
+                sink.lock().unwrap().push(event.kind);
+            }
+        });
+
+        let mut box_ = MangledBox::<Odd>::new();
+        box_.with_unmangled(|_| {});
+        box_.rekey();
+        drop(box_);
+        clear_access_observer();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [AccessKind::Unmangle, AccessKind::Rekey, AccessKind::Drop]
+        );
+    }
+}
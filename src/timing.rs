@@ -0,0 +1,110 @@
+//! Auditing of how long secrets stay unmasked, see [`MangledBox::with_unmangled_timed`].
+//!
+//! Only non-secret metadata reaches the handler: the measured duration, the limit and the
+//! size of the masked value.
+//!
+//! [`MangledBox::with_unmangled_timed`]: crate::MangledBox::with_unmangled_timed
+
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// Report of a closure that kept a secret unmasked for longer than allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Overrun {
+    /// How long the closure ran.
+    pub elapsed: Duration,
+    /// The limit it exceeded.
+    pub max: Duration,
+    /// `size_of::<T>()` of the container's value type.
+    pub size: usize,
+}
+
+type Handler = Arc<dyn Fn(Overrun) + Send + Sync>;
+
+static HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+
+/// Installs `handler` to be called on every overrun, replacing the previous one. Without
+/// a handler, overruns are logged to standard error.
+///
+/// The handler runs on the accessing thread, after the closure and before the contents
+/// are remangled, possibly while a panic from the closure is unwinding; panicking in it
+/// then aborts the process.
+pub fn set_overrun_handler(handler: impl Fn(Overrun) + Send + Sync + 'static) {
+    *HANDLER.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(handler));
+}
+
+/// Removes the handler, if any, restoring the default of logging to standard error.
+pub fn clear_overrun_handler() {
+    *HANDLER.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Measures an unmasked window, reporting an overrun when dropped.
+pub(crate) struct OverrunCheck {
+    start: Instant,
+    max: Duration,
+    size: usize,
+}
+
+impl OverrunCheck {
+    /// Starts measuring a window for a `size`-byte value, allowed to last `max`.
+    pub(crate) fn start(max: Duration, size: usize) -> Self {
+        Self { start: Instant::now(), max, size }
+    }
+}
+
+impl Drop for OverrunCheck {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        if elapsed <= self.max {
+            return;
+        }
+        let overrun = Overrun { elapsed, max: self.max, size: self.size };
+
+        // The lock is released before calling, so that the handler may access containers
+        // or replace itself.
+        let handler = HANDLER.read().unwrap_or_else(PoisonError::into_inner).clone();
+        match handler {
+            Some(handler) => handler(overrun),
+            None => eprintln!(
+                "secretmangle: a {}-byte secret stayed unmasked for {:?}, over the {:?} limit",
+                overrun.size, overrun.elapsed, overrun.max
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::MangledBox;
+
+    #[test]
+    fn reports_overruns() {
+        // Other tests run concurrently, so only overruns for this odd size are collected.
+        const SIZE: usize = 29;
+
+        let overruns = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&overruns);
+        set_overrun_handler(move |overrun| {
+            if overrun.size == SIZE {
+                sink.lock().unwrap().push(overrun);
+            }
+        });
+
+        let mut box_ = MangledBox::<[u8; SIZE]>::new();
+        let value = box_.with_unmangled_timed(Duration::from_secs(3600), |_| 5);
+        assert_eq!(value, 5);
+        assert!(overruns.lock().unwrap().is_empty());
+
+        box_.with_unmangled_timed(Duration::ZERO, |_| std::thread::sleep(Duration::from_millis(2)));
+        clear_overrun_handler();
+
+        let overruns = overruns.lock().unwrap();
+        assert_eq!(overruns.len(), 1);
+        assert_eq!(overruns[0].max, Duration::ZERO);
+        assert!(overruns[0].elapsed >= Duration::from_millis(2));
+    }
+}